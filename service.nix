@@ -7,6 +7,7 @@
 }:
 let
   botBin = lib.getExe self.packages.${pkgs.system}.default;
+  cfg = config.services.claude-discord-bot;
 in
 with lib;
 {
@@ -18,6 +19,49 @@ with lib;
       description = "Path containing the Discord bot token";
     };
 
+    masterKeyFile = mkOption {
+      type = types.str;
+      description = "Path containing the base64 encoded key Claude API keys are encrypted with";
+    };
+
+    defaultApiKeyFile = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      description = "Path containing an Anthropic API key for allowed servers without their own";
+    };
+
+    defaultApiKeyGuildIds = mkOption {
+      type = types.listOf types.str;
+      default = [ ];
+      description = "IDs of the servers allowed to use the default API key";
+    };
+
+    defaultApiKeyDailyTokens = mkOption {
+      type = types.nullOr types.ints.positive;
+      default = null;
+      description = "Most tokens the default API key may be used for per UTC day";
+    };
+
+    configFile = mkOption {
+      type = types.nullOr types.path;
+      default = null;
+      description = "Path to a TOML file of bot-wide settings and new server defaults";
+    };
+
+    metricsAddress = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "127.0.0.1:9100";
+      description = "Address to serve Prometheus metrics on";
+    };
+
+    healthAddress = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "127.0.0.1:9101";
+      description = "Address to serve /healthz and /readyz on";
+    };
+
     databasePath = mkOption {
       type = types.path;
       default = "/var/lib/claude-discord-bot/bot.redb";
@@ -29,6 +73,15 @@ with lib;
       default = "INFO";
       description = "Log level, one of (INFO, WARN, ERROR, DEBUG, TRACE)";
     };
+
+    logFormat = mkOption {
+      type = types.enum [
+        "text"
+        "json"
+      ];
+      default = "text";
+      description = "How log lines are written";
+    };
   };
 
   config = mkIf config.services.claude-discord-bot.enable {
@@ -38,12 +91,26 @@ with lib;
       wantedBy = [ "multi-user.target" ];
 
       serviceConfig = {
-        ExecStart = lib.concatStringsSep " " [
-          "${botBin}"
-          "--discord-token-file ${toString config.services.claude-discord-bot.discordTokenFile}"
-          "--database-path ${config.services.claude-discord-bot.databasePath}"
-          "--log-level ${config.services.claude-discord-bot.logLevel}"
-        ];
+        ExecStart = lib.concatStringsSep " " (
+          [
+            "${botBin}"
+            "--database-path ${config.services.claude-discord-bot.databasePath}"
+            "--log-level ${config.services.claude-discord-bot.logLevel}"
+            "--log-format ${cfg.logFormat}"
+          ]
+          ++ lib.optional (cfg.configFile != null) "--config ${toString cfg.configFile}"
+          ++ lib.optional (cfg.metricsAddress != null) "--metrics-address ${cfg.metricsAddress}"
+          ++ lib.optional (cfg.healthAddress != null) "--health-address ${cfg.healthAddress}"
+          ++ map (id: "--default-api-key-guild-id ${id}") cfg.defaultApiKeyGuildIds
+          ++ lib.optional (
+            cfg.defaultApiKeyDailyTokens != null
+          ) "--default-api-key-daily-tokens ${toString cfg.defaultApiKeyDailyTokens}"
+        );
+        LoadCredential = [
+          "discord-token:${toString cfg.discordTokenFile}"
+          "master-key:${toString cfg.masterKeyFile}"
+        ]
+        ++ lib.optional (cfg.defaultApiKeyFile != null) "default-api-key:${cfg.defaultApiKeyFile}";
         StateDirectory = "claude-discord-bot";
         StateDirectoryMode = "0700";
         Restart = "always";
@@ -1,8 +1,11 @@
-use std::{io::Read, path::PathBuf};
+use std::{io::Read, net::SocketAddr, num::NonZeroU64, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 
-fn validate_nonempty_readable_token_file(s: &str) -> Result<String, String> {
+use crate::config::Config;
+use crate::secret::Secret;
+
+fn validate_nonempty_readable_token_file(s: &str) -> Result<Secret, String> {
     let path = PathBuf::from(s);
 
     if !path.exists() {
@@ -23,26 +26,171 @@ fn validate_nonempty_readable_token_file(s: &str) -> Result<String, String> {
             if file_contents.is_empty() {
                 Err(format!("'{s}' is an empty file"))
             } else {
-                Ok(file_contents.trim().to_string())
+                Ok(Secret::new(&file_contents))
             }
         }
         Err(e) => Err(format!("Cannot read file '{s}': {e}")),
     }
 }
 
+fn load_config_file(s: &str) -> Result<Config, String> {
+    Config::load(PathBuf::from(s)).map_err(|e| e.to_string())
+}
+
 /// CLI interface for the Claude Discord bot
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Path to file containing (only) a Discord token
+    /// Path to file containing (only) a Discord token [or
+    /// `$CLAUDE_DISCORD_BOT_DISCORD_TOKEN`, or the `discord-token` credential]
     #[arg(short('t'), long, value_parser = validate_nonempty_readable_token_file)]
-    pub discord_token_file: String,
+    pub discord_token_file: Option<Secret>,
+
+    /// Path to file containing (only) the base64 encoded 32 byte key Claude API
+    /// keys are encrypted with, e.g. from `openssl rand -base64 32` [or
+    /// `$CLAUDE_DISCORD_BOT_MASTER_KEY`, or the `master-key` credential]
+    #[arg(short, long, value_parser = validate_nonempty_readable_token_file)]
+    pub master_key_file: Option<Secret>,
+
+    /// Path to file containing (only) an Anthropic API key for servers without
+    /// their own to use, if they're allowed to [or
+    /// `$CLAUDE_DISCORD_BOT_DEFAULT_API_KEY`, or the `default-api-key`
+    /// credential]
+    #[arg(long, value_parser = validate_nonempty_readable_token_file)]
+    pub default_api_key_file: Option<Secret>,
+
+    /// ID of a server allowed to use the default API key (repeatable)
+    #[arg(long = "default-api-key-guild-id", value_name = "GUILD_ID")]
+    pub default_api_key_guild_ids: Vec<u64>,
+
+    /// Most tokens (input and output) the default API key may be used for per
+    /// UTC day, across all servers [default: unlimited]
+    #[arg(long)]
+    pub default_api_key_daily_tokens: Option<NonZeroU64>,
+
+    /// Path to a TOML file of bot-wide settings and the config new servers
+    /// start with
+    #[arg(short, long, global = true, value_parser = load_config_file)]
+    pub config: Option<Config>,
+
+    /// Address to serve Prometheus metrics on, at `/metrics`, e.g.
+    /// 127.0.0.1:9100 [default: not served]
+    #[arg(long)]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Address to serve `/healthz` and `/readyz` on, e.g. 127.0.0.1:9101
+    /// [default: not served]
+    #[arg(long)]
+    pub health_address: Option<SocketAddr>,
 
     /// Path to database file
-    #[arg(short, long, default_value = "./claude_discord_bot.redb")]
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value = "./claude_discord_bot.redb"
+    )]
     pub database_path: PathBuf,
 
     /// Log level, one of (INFO, WARN, ERROR, DEBUG, TRACE)
-    #[arg(short, long, default_value_t = tracing::Level::INFO)]
+    #[arg(short, long, global = true, default_value_t = tracing::Level::INFO)]
     pub log_level: tracing::Level,
+
+    /// How log lines are written. Both include the guild, channel, message
+    /// and request ids of the message being responded to.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Offline tasks, run instead of the bot
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Export feedback on Claude's messages as JSONL
+    ExportFeedback {
+        /// Only export feedback from this Discord server
+        #[arg(short, long)]
+        guild_id: Option<u64>,
+
+        /// File to write to, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Re-encrypt every stored Claude API key with a new master key. Keys
+    /// stored before encryption was enabled are encrypted too.
+    RotateMasterKey {
+        /// Path to file containing (only) the new master key
+        #[arg(short, long, value_parser = validate_nonempty_readable_token_file)]
+        new_master_key_file: Secret,
+    },
+
+    /// Inspect and change stored server configs directly, while the bot is
+    /// stopped
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+/// Offline server config management
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// List the servers with a stored config
+    List,
+
+    /// Print a server's config as JSON, without its API key
+    Show { guild_id: u64 },
+
+    /// Set one field of a server's config, as named in `show`'s output
+    Edit {
+        guild_id: u64,
+
+        field: String,
+
+        /// JSON value, e.g. `true`, `null` or `[123, 456]`. Anything that
+        /// isn't JSON is taken as a string.
+        value: String,
+    },
+
+    /// Export every server's config as JSON. API keys stay encrypted with the
+    /// current master key.
+    Export {
+        /// File to write to, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Import configs from `export`, replacing those of the same servers
+    Import {
+        /// File to read from
+        input: PathBuf,
+    },
+
+    /// Remove a server's config, so it starts over with the default one
+    Remove { guild_id: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use clap::Parser;
+
+    #[test]
+    fn secrets_not_in_debug_output() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "discord-secret").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let args = Args::parse_from(["claude-discord-bot", "-t", path, "-m", path]);
+
+        assert!(!format!("{args:?}").contains("discord-secret"));
+        assert_eq!(args.discord_token_file.unwrap().expose(), "discord-secret");
+    }
 }
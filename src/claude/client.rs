@@ -1,21 +1,41 @@
 use super::consts;
-use super::model::Model;
+use super::model::{Model, ModelInfo};
 use super::response::Response;
-use super::system_prompt::SYSTEM_PROMPT;
+use super::system_prompt::{default_system_prompt, prompt_version};
 use super::tools::ToolDefinition;
 use crate::claude;
+use crate::config::ClaudeConfig;
+use crate::metrics;
+use dashmap::DashMap;
+use reqwest::StatusCode;
+use serde::Deserialize;
 use std::num::NonZeroU64;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 use consts::ANTHROPIC_API_BASE_URL;
 
+/// How long the models an API key can use are cached for
+const MODEL_CACHE_TTL: Duration = Duration::from_hours(1);
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+struct CachedModels {
+    fetched: Instant,
+    models: Arc<Vec<ModelInfo>>,
+}
+
 pub trait GetResponse {
     async fn get_response(
         &self,
         msgs: &[claude::Message],
         api_key: &str,
         model: &claude::Model,
+        now: &str,
     ) -> Result<claude::Response, ClaudeError>;
 }
 
@@ -25,8 +45,9 @@ impl GetResponse for Client {
         msgs: &[claude::Message],
         api_key: &str,
         model: &claude::Model,
+        now: &str,
     ) -> Result<claude::Response, ClaudeError> {
-        self.get_response(msgs, api_key, model).await
+        self.get_response(msgs, api_key, model, now).await
     }
 }
 
@@ -36,6 +57,8 @@ pub enum ClaudeError {
     Http(reqwest::Error),
     #[error("Couldn't deserialize response ({0})")]
     Parse(reqwest::Error),
+    #[error("Unexpected response status ({0})")]
+    Status(StatusCode),
 }
 
 #[derive(Clone)]
@@ -44,52 +67,144 @@ pub struct Client {
     system_prompt: Arc<String>,
     anthropic_version: Arc<String>,
     max_tokens: NonZeroU64,
+    message_context_length: u8,
     tools: Arc<Vec<ToolDefinition>>,
+    /// Keyed by a hash of the API key, so the cache doesn't hold onto keys
+    model_cache: Arc<DashMap<Vec<u8>, CachedModels>>,
 }
 
 impl Client {
-    pub fn new() -> Self {
+    pub fn with_config(config: &ClaudeConfig) -> Self {
         Self {
-            ..Default::default()
+            http: reqwest::Client::new(),
+            anthropic_version: config.anthropic_version.clone().into(),
+            max_tokens: config.max_tokens,
+            message_context_length: config.message_context_length,
+            system_prompt: config
+                .system_prompt
+                .clone()
+                .unwrap_or_else(|| default_system_prompt(config.message_context_length))
+                .into(),
+            tools: ToolDefinition::get_tools().into(),
+            model_cache: Arc::default(),
         }
     }
 
+    /// How many of the most recent messages Claude is given
+    pub fn message_context_length(&self) -> u8 {
+        self.message_context_length
+    }
+
+    pub fn system_prompt_version(&self) -> String {
+        prompt_version(&self.system_prompt)
+    }
+
     pub async fn get_response(
         &self,
         msgs: &[claude::Message],
         api_key: &str,
         model: &Model,
+        now: &str,
     ) -> Result<Response, ClaudeError> {
-        let request = super::Request::new(
-            model,
-            &self.system_prompt,
-            self.max_tokens,
-            &self.tools,
-            msgs,
+        let system_prompt = format!(
+            "{}\n<current_time>\nIt is currently {now}.\n</current_time>\n",
+            self.system_prompt
         );
 
-        self.http
+        let max_tokens = model
+            .capabilities()
+            .and_then(|c| NonZeroU64::new(c.max_output_tokens))
+            .map_or(self.max_tokens, |max| self.max_tokens.min(max));
+
+        let request = super::Request::new(model, &system_prompt, max_tokens, &self.tools, msgs);
+
+        let started = Instant::now();
+        let response = self.send(&request, api_key).await;
+        metrics::record_claude_request(model, &response, started.elapsed());
+
+        response
+    }
+
+    async fn send(
+        &self,
+        request: &super::Request<'_>,
+        api_key: &str,
+    ) -> Result<Response, ClaudeError> {
+        let response = self
+            .http
             .post(format!("{ANTHROPIC_API_BASE_URL}/messages"))
             .header("x-api-key", api_key)
             .header("anthropic-version", self.anthropic_version.to_string())
-            .json(&request)
+            .json(request)
             .send()
             .await
-            .map_err(ClaudeError::Http)?
-            .json()
+            .map_err(ClaudeError::Http)?;
+
+        // Anthropic asks for this ID when looking into a request, so it's
+        // added to the span of the message being responded to, if any
+        if let Some(request_id) = response
+            .headers()
+            .get("request-id")
+            .and_then(|id| id.to_str().ok())
+        {
+            tracing::Span::current().record("request_id", request_id);
+            log::debug!("Claude request id {request_id} ({})", response.status());
+        }
+
+        response.json().await.map_err(ClaudeError::Parse)
+    }
+
+    /// The models `api_key` can use, newest first, or `None` if Anthropic
+    /// doesn't accept it
+    pub async fn models(&self, api_key: &str) -> Result<Option<Arc<Vec<ModelInfo>>>, ClaudeError> {
+        let cache_key = ring::digest::digest(&ring::digest::SHA256, api_key.as_bytes())
+            .as_ref()
+            .to_vec();
+
+        if let Some(cached) = self.model_cache.get(&cache_key)
+            && cached.fetched.elapsed() < MODEL_CACHE_TTL
+        {
+            return Ok(Some(cached.models.clone()));
+        }
+
+        let response = self
+            .http
+            .get(format!("{ANTHROPIC_API_BASE_URL}/models"))
+            .query(&[("limit", "1000")])
+            .header("x-api-key", api_key)
+            .header("anthropic-version", self.anthropic_version.to_string())
+            .send()
             .await
-            .map_err(ClaudeError::Parse)
+            .map_err(ClaudeError::Http)?;
+
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Ok(None),
+            status => return Err(ClaudeError::Status(status)),
+        }
+
+        let models = Arc::new(
+            response
+                .json::<ModelList>()
+                .await
+                .map_err(ClaudeError::Parse)?
+                .data,
+        );
+
+        self.model_cache.insert(
+            cache_key,
+            CachedModels {
+                fetched: Instant::now(),
+                models: models.clone(),
+            },
+        );
+
+        Ok(Some(models))
     }
 }
 
 impl Default for Client {
     fn default() -> Self {
-        Self {
-            http: reqwest::Client::new(),
-            anthropic_version: consts::ANTHROPIC_API_VERSION.to_string().into(),
-            max_tokens: NonZeroU64::new(2048).unwrap(),
-            system_prompt: SYSTEM_PROMPT.to_string().into(),
-            tools: ToolDefinition::get_tools().into(),
-        }
+        Self::with_config(&ClaudeConfig::default())
     }
 }
@@ -1,2 +1 @@
-pub const ANTHROPIC_API_VERSION: &str = "2023-06-01";
 pub const ANTHROPIC_API_BASE_URL: &str = "https://api.anthropic.com/v1";
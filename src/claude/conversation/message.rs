@@ -1,5 +1,5 @@
 use super::{ContentBlock, ImageBlock, TextBlock};
-use crate::discord::NormalizeContent;
+use crate::discord::{LocalTime, NormalizeContent};
 
 use poise::serenity_prelude as serenity;
 
@@ -18,20 +18,20 @@ pub struct Message {
 }
 
 impl Message {
-    fn format_message(msg: &serenity::Message) -> String {
+    pub fn format_message(
+        msg: &serenity::Message,
+        cache: &serenity::Cache,
+        local_time: LocalTime,
+    ) -> String {
         let fmt = |msg: &serenity::Message, reply_context: String| {
-            let time = msg
-                .timestamp
-                .with_timezone(&chrono::Local)
-                .format("%-m-%-d-%Y %-I:%M%p")
-                .to_string();
+            let time = local_time.format(*msg.timestamp, "%-m-%-d-%Y %-I:%M%p");
 
             format!(
                 "[{}] {}:{} {}",
                 time,
                 msg.author.display_name(),
                 reply_context,
-                msg.normalize_content(),
+                msg.normalize_content(cache, local_time),
             )
         };
 
@@ -122,9 +122,44 @@ impl Message {
             .peekable()
     }
 
+    pub fn user_text(text: &str) -> Self {
+        Message {
+            role: Role::User,
+            content: Content::Text(text.to_string()),
+        }
+    }
+
+    /// A message sent through a slash command rather than in the channel
+    pub fn from_command(
+        author_name: &str,
+        time: &str,
+        text: &str,
+        image_url: Option<&str>,
+    ) -> Self {
+        let text = format!("[{time}] {author_name}: {text}");
+
+        let Some(url) = image_url else {
+            return Message::user_text(&text);
+        };
+
+        Message {
+            role: Role::User,
+            content: Content::ContentBlocks(vec![
+                ContentBlock::Text(TextBlock {
+                    text: format!("*@{author_name} uploaded the following image*"),
+                }),
+                ContentBlock::ImageBlock(ImageBlock {
+                    url: url.to_string(),
+                }),
+                ContentBlock::Text(TextBlock { text }),
+            ]),
+        }
+    }
+
     pub fn from(
         discord_message: &serenity::Message,
         context: &serenity::Context,
+        local_time: LocalTime,
     ) -> impl Iterator<Item = Self> {
         let role = if discord_message.author.id == context.cache.current_user().id {
             Role::Assistant
@@ -132,7 +167,7 @@ impl Message {
             Role::User
         };
 
-        let message_text = Message::format_message(discord_message);
+        let message_text = Message::format_message(discord_message, &context.cache, local_time);
         let claude_message =
             Message::with_contextualized_images(discord_message, &message_text, role);
         let bot_reactions = Message::bot_reactions(discord_message).collect_vec();
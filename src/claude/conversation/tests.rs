@@ -196,3 +196,39 @@ fn mixed_content_type() {
 
     assert_eq!(msgs, json);
 }
+
+#[test]
+fn command_message_with_image() {
+    let msgs = serde_json::to_value(vec![Message::from_command(
+        "alice",
+        "7-4-2025 1:00PM",
+        "what is this?",
+        Some("url goes here"),
+    )])
+    .unwrap();
+
+    let json = serde_json::json!([
+        {
+          "role": "user",
+          "content": [
+            {
+              "type": "text",
+              "text": "*@alice uploaded the following image*",
+            },
+            {
+              "type": "image",
+              "source": {
+                "type": "url",
+                "url": "url goes here",
+              },
+            },
+            {
+              "type": "text",
+              "text": "[7-4-2025 1:00PM] alice: what is this?",
+            },
+          ],
+        },
+    ]);
+
+    assert_eq!(msgs, json);
+}
@@ -9,13 +9,8 @@ mod tools;
 
 pub use client::{ClaudeError, Client, GetResponse};
 pub use conversation::Message;
-pub use model::Model;
+pub use model::{Model, ModelInfo};
 pub use request::Request;
-pub use response::{Action, Response, StopReason};
-
-#[cfg(test)]
-pub use response::Usage;
+pub use response::{Action, Response, StopReason, Usage};
 
 pub use tools::ToolDefinition;
-
-pub use system_prompt::MESSAGE_CONTEXT_LENGTH;
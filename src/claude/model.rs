@@ -1,149 +1,205 @@
 use bincode::{Decode, Encode};
-use clap::ValueEnum;
-use poise::ChoiceParameter;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
-#[derive(Clone, Debug, ValueEnum, Deserialize, Encode, Decode, ChoiceParameter)]
-pub enum Model {
-    #[name = "Opus 4.6"]
-    #[value(name = "opus-4.6")]
-    Opus46,
-    #[name = "Sonnet 4.6"]
-    #[value(name = "sonnet-4.6")]
-    Sonnet46,
-
-    #[name = "Opus 4.5"]
-    #[value(name = "opus-4.5")]
-    Opus45,
-    #[name = "Sonnet 4.5"]
-    #[value(name = "sonnet-4.5")]
-    Sonnet45,
-    #[name = "Haiku 4.5"]
-    #[value(name = "haiku-4.5")]
-    Haiku45,
-
-    #[name = "Opus 4.1"]
-    #[value(name = "opus-4.1")]
-    Opus41,
-
-    #[name = "Opus 4"]
-    #[value(name = "opus-4")]
-    Opus4,
-    #[name = "Sonnet 4"]
-    #[value(name = "sonnet-4")]
-    Sonnet4,
+/// What a family of models can do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub vision: bool,
+    pub thinking: bool,
+    pub max_output_tokens: u64,
 }
 
-impl Model {
-    pub fn id(&self) -> String {
-        match self {
-            Model::Opus46 => "claude-opus-4-6",
-            Model::Sonnet46 => "claude-sonnet-4-6",
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if self.vision {
+            parts.push("vision".to_string());
+        }
+        if self.thinking {
+            parts.push("extended thinking".to_string());
+        }
+        parts.push(format!("up to {} output tokens", self.max_output_tokens));
 
-            Model::Opus45 => "claude-opus-4-5",
-            Model::Sonnet45 => "claude-sonnet-4-5",
-            Model::Haiku45 => "claude-haiku-4-5",
+        f.write_str(&parts.join(", "))
+    }
+}
 
-            Model::Opus41 => "claude-opus-4-1",
+/// A family of models the bot knows about, which covers its alias and dated
+/// snapshots
+struct Family {
+    /// What the snapshot IDs start with, before `-YYYYMMDD`
+    id_prefix: &'static str,
+    alias: &'static str,
+    name: &'static str,
+    capabilities: Capabilities,
+}
 
-            Model::Opus4 => "claude-opus-4-0",
-            Model::Sonnet4 => "claude-sonnet-4-0",
-        }
-        .to_string()
+impl Family {
+    /// Whether `id` is the family's alias or one of its snapshots
+    fn covers(&self, id: &str) -> bool {
+        id == self.alias
+            || id
+                .strip_prefix(self.id_prefix)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(|date| date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()))
     }
+}
 
-    pub fn pretty_name(&self) -> String {
-        String::from(match self {
-            Model::Opus46 => "Opus 4.6",
-            Model::Sonnet46 => "Sonnet 4.6",
+const fn family(
+    id_prefix: &'static str,
+    alias: &'static str,
+    name: &'static str,
+    max_output_tokens: u64,
+) -> Family {
+    Family {
+        id_prefix,
+        alias,
+        name,
+        capabilities: Capabilities {
+            vision: true,
+            thinking: true,
+            max_output_tokens,
+        },
+    }
+}
+
+const FAMILIES: &[Family] = &[
+    family("claude-opus-4-6", "claude-opus-4-6", "Opus 4.6", 128_000),
+    family(
+        "claude-sonnet-4-6",
+        "claude-sonnet-4-6",
+        "Sonnet 4.6",
+        64_000,
+    ),
+    family("claude-opus-4-5", "claude-opus-4-5", "Opus 4.5", 64_000),
+    family(
+        "claude-sonnet-4-5",
+        "claude-sonnet-4-5",
+        "Sonnet 4.5",
+        64_000,
+    ),
+    family("claude-haiku-4-5", "claude-haiku-4-5", "Haiku 4.5", 64_000),
+    family("claude-opus-4-1", "claude-opus-4-1", "Opus 4.1", 32_000),
+    family("claude-opus-4", "claude-opus-4-0", "Opus 4", 32_000),
+    family("claude-sonnet-4", "claude-sonnet-4-0", "Sonnet 4", 64_000),
+];
+
+/// A Claude model, by its ID or alias (e.g. `claude-sonnet-4-0`)
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Model {
+    id: String,
+}
+
+impl Model {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.to_string() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-            Model::Opus45 => "Opus 4.5",
-            Model::Sonnet45 => "Sonnet 4.5",
-            Model::Haiku45 => "Haiku 4.5",
+    fn family(&self) -> Option<&'static Family> {
+        FAMILIES.iter().find(|f| f.covers(&self.id))
+    }
 
-            Model::Opus41 => "Opus 4.1",
+    /// Whether `models` include this one, or it's an alias for a family they
+    /// include a snapshot of
+    pub fn is_among(&self, models: &[ModelInfo]) -> bool {
+        let alias_of = FAMILIES.iter().find(|f| f.alias == self.id);
 
-            Model::Opus4 => "Opus 4",
-            Model::Sonnet4 => "Sonnet 4",
-        })
+        models
+            .iter()
+            .any(|m| m.id == self.id || alias_of.is_some_and(|f| f.covers(&m.id)))
+    }
+
+    /// The model's family (e.g. "Sonnet 4"), or its ID if it isn't recognized
+    pub fn pretty_name(&self) -> String {
+        self.family()
+            .map_or_else(|| self.id.clone(), |f| f.name.to_string())
+    }
+
+    /// What the model can do, if it's recognized
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.family().map(|f| f.capabilities)
     }
 }
 
 impl Default for Model {
     fn default() -> Self {
-        Self::Sonnet4
+        Self::new("claude-sonnet-4-0")
     }
 }
 
 impl Display for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(self.to_possible_value().unwrap().get_name())
+        f.write_str(&self.id)
     }
 }
 
-impl Serialize for Model {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&self.id())
-    }
+/// A model as listed by Anthropic's models endpoint
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub id: String,
+    pub display_name: String,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Model;
-    use crate::claude::consts::{ANTHROPIC_API_BASE_URL, ANTHROPIC_API_VERSION};
-    use clap::ValueEnum;
-    use poise::serenity_prelude::futures::future::join_all;
-    use reqwest::header::HeaderName;
+    use super::{Model, ModelInfo};
+    use crate::claude::Client;
+
+    #[test]
+    fn families_recognized_by_alias_and_snapshot() {
+        assert_eq!(Model::new("claude-opus-4-0").pretty_name(), "Opus 4");
+        assert_eq!(Model::new("claude-opus-4-20250514").pretty_name(), "Opus 4");
+        assert_eq!(
+            Model::new("claude-opus-4-1-20250805").pretty_name(),
+            "Opus 4.1"
+        );
+        assert_eq!(
+            Model::new("claude-sonnet-4-5")
+                .capabilities()
+                .unwrap()
+                .max_output_tokens,
+            64_000
+        );
+
+        let listed = [ModelInfo {
+            id: "claude-sonnet-4-20250514".to_string(),
+            display_name: "Claude Sonnet 4".to_string(),
+        }];
+        assert!(Model::new("claude-sonnet-4-0").is_among(&listed));
+        assert!(Model::new("claude-sonnet-4-20250514").is_among(&listed));
+        assert!(!Model::new("claude-sonnet-4-5").is_among(&listed));
+        assert!(!Model::new("claude-sonnet-4-typo").is_among(&listed));
+        assert!(!Model::new("claude-sonnet-4-20250101").is_among(&listed));
+
+        let unknown = Model::new("claude-future-9");
+        assert_eq!(unknown.pretty_name(), "claude-future-9");
+        assert!(unknown.capabilities().is_none());
+
+        // Newer versions of a family aren't mistaken for it
+        assert!(Model::new("claude-opus-4-7").capabilities().is_none());
+        assert!(Model::new("claude-opus-4-7-20260101").family().is_none());
+    }
 
     #[cfg_attr(not(feature = "api_key_tests"), ignore = "ig")]
     #[tokio::test]
-    async fn valid_model_ids() {
-        let client = reqwest::ClientBuilder::new()
-            .default_headers(
-                [
-                    (
-                        HeaderName::from_static("x-api-key"),
-                        std::env::var("ANTHROPIC_API_KEY").unwrap().parse().unwrap(),
-                    ),
-                    (
-                        HeaderName::from_static("anthropic-version"),
-                        ANTHROPIC_API_VERSION.parse().unwrap(),
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            )
-            .build()
-            .unwrap();
-
-        let response_futures = Model::value_variants()
-            .iter()
-            .map(super::Model::id)
-            .map(|id| {
-                client
-                    .get(format!("{ANTHROPIC_API_BASE_URL}/models/{id}"))
-                    .send()
-            })
-            .collect::<Vec<_>>();
-
-        let responses = join_all(response_futures).await;
-
-        for res in &responses {
-            match res {
-                Err(e) => panic!("Error sending request: {e}"),
-                Ok(res) => {
-                    assert!(
-                        res.status() == 200,
-                        "Model ID {} is invalid (status code {})",
-                        res.url().path(),
-                        res.status()
-                    );
-                }
-            }
-        }
+    async fn default_model_available() {
+        let models = Client::default()
+            .models(&std::env::var("ANTHROPIC_API_KEY").unwrap())
+            .await
+            .unwrap()
+            .expect("API key should be valid");
+
+        assert!(
+            models
+                .iter()
+                .any(|m| m.id.starts_with("claude-sonnet-4") || m.id == Model::default().id()),
+            "No Sonnet 4 model in {models:?}"
+        );
     }
 }
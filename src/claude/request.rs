@@ -51,7 +51,7 @@ mod tests {
         let skip_response_tool = ToolDefinition::get_tools().get(2).unwrap().clone();
 
         let request = serde_json::to_value(Request {
-            model: &Model::Sonnet4,
+            model: &Model::new("claude-sonnet-4-0"),
             system: "system prompt",
             max_tokens: NonZeroU64::new(1024).unwrap(),
             tool_choice: json!({"type": "any"}),
@@ -95,7 +95,7 @@ mod tests {
         let message_and_react_tools = ToolDefinition::get_tools().into_iter().take(2);
 
         let request = serde_json::to_value(Request {
-            model: &Model::Opus46,
+            model: &Model::new("claude-opus-4-6"),
             system: "complicated system prompt",
             max_tokens: NonZeroU64::new(1024).unwrap(),
             tool_choice: json!({"type": "any"}),
@@ -29,7 +29,21 @@ pub enum StopReason {
     Refusal,
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+impl StopReason {
+    /// As the API names it
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::EndTurn => "end_turn",
+            StopReason::MaxTokens => "max_tokens",
+            StopReason::StopSequence => "stop_sequence",
+            StopReason::ToolUse => "tool_use",
+            StopReason::PauseTurn => "pause_turn",
+            StopReason::Refusal => "refusal",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
 pub struct Usage {
     pub input_tokens: u64,
     pub output_tokens: u64,
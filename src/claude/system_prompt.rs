@@ -1,7 +1,18 @@
-pub const MESSAGE_CONTEXT_LENGTH: u8 = 15;
+/// A short, stable identifier for a system prompt's text, so feedback can be
+/// attributed to the prompt that produced it
+pub fn prompt_version(prompt: &str) -> String {
+    // 64-bit FNV-1a, which unlike `DefaultHasher` is stable across releases
+    let hash = prompt.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
 
-pub const SYSTEM_PROMPT: &str = const_format::formatcp!(
-    "
+    format!("{hash:016x}")
+}
+
+/// The system prompt used unless the config file replaces it
+pub fn default_system_prompt(message_context_length: u8) -> String {
+    format!(
+        "
 <instructions>
 You are a helpful assistant participating in a Discord server. You should:
 - Be conversational and friendly
@@ -21,9 +32,27 @@ Images are represented as image blocks, and each will be preceded by a text bloc
 </formatting>
 
 <context>
-Messages with content containing '@Claude' mean you were mentioned directly.
+Messages with content containing '@Claude' mean you were mentioned directly. To mention someone in your own messages, write '@' followed by their name.
 
-You are provided with {MESSAGE_CONTEXT_LENGTH} of the most recent messages. However, if you choose to respond, please do so only to the most recent message.
+You are provided with {message_context_length} of the most recent messages. However, if you choose to respond, please do so only to the most recent message.
 </context>
 "
-);
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_system_prompt, prompt_version};
+
+    #[test]
+    fn prompt_version_is_stable() {
+        assert_eq!(prompt_version(""), "cbf29ce484222325");
+        assert_eq!(prompt_version("a"), "af63dc4c8601ec8c");
+        assert_ne!(prompt_version("prompt"), prompt_version("prompt "));
+        // Changing the default prompt changes the version feedback is recorded with
+        assert_eq!(
+            prompt_version(&default_system_prompt(15)),
+            "9a8770d52ff5892b"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::claude::Model;
+use crate::database::Record;
+use crate::discord::{DeletedTriggerAction, MentionPolicy, RateLimit};
+
+/// The message being responded to, and at least one before it, since Discord
+/// won't return an empty history
+const MIN_MESSAGE_CONTEXT_LENGTH: u8 = 2;
+
+/// Most messages Discord returns for one history request
+const MAX_MESSAGE_CONTEXT_LENGTH: u8 = 100;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Cannot read config file '{0}' ({1})")]
+    Read(PathBuf, std::io::Error),
+
+    #[error("Invalid config file '{0}'\n{1}")]
+    Parse(PathBuf, toml::de::Error),
+
+    #[error("Invalid config file '{0}': `{1}` {2}")]
+    Invalid(PathBuf, &'static str, String),
+}
+
+/// Bot-wide settings from the `--config` file. Anything left out keeps its
+/// default.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub claude: ClaudeConfig,
+    pub discord: DiscordConfig,
+    pub new_server: NewServerConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClaudeConfig {
+    /// Most tokens Claude may respond with, lowered to what the model allows
+    pub max_tokens: NonZeroU64,
+    /// Sent as the `anthropic-version` header
+    pub anthropic_version: String,
+    /// How many of the most recent messages Claude is given
+    pub message_context_length: u8,
+    /// Replaces the built-in system prompt
+    pub system_prompt: Option<String>,
+}
+
+impl Default for ClaudeConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: NonZeroU64::new(2048).unwrap(),
+            anthropic_version: "2023-06-01".to_string(),
+            message_context_length: 15,
+            system_prompt: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    /// How many messages can wait for each channel's task before new ones
+    /// wait to be queued
+    pub channel_queue_size: NonZeroUsize,
+    /// How long to wait for queued messages to be handled when shutting down
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            channel_queue_size: NonZeroUsize::new(128).unwrap(),
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// The config servers start with, before it's changed with slash commands
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NewServerConfig {
+    pub model: Model,
+    pub random_interaction_chance_denominator: Option<NonZeroU64>,
+    pub mention_policy: MentionPolicy,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub regenerate_on_edit: bool,
+    pub deleted_trigger_action: DeletedTriggerAction,
+    pub user_rate_limit: Option<RateLimit>,
+    pub channel_rate_limit: Option<RateLimit>,
+    pub guild_rate_limit: Option<RateLimit>,
+}
+
+impl NewServerConfig {
+    pub fn record(&self) -> Record {
+        Record {
+            model: self.model.clone(),
+            random_interaction_chance_denominator: self.random_interaction_chance_denominator,
+            mention_policy: self.mention_policy.clone(),
+            timezone: self.timezone.clone(),
+            locale: self.locale.clone(),
+            regenerate_on_edit: self.regenerate_on_edit,
+            deleted_trigger_action: self.deleted_trigger_action.clone(),
+            user_rate_limit: self.user_rate_limit,
+            channel_rate_limit: self.channel_rate_limit,
+            guild_rate_limit: self.guild_rate_limit,
+            ..Default::default()
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: PathBuf) -> Result<Self, ConfigError> {
+        let contents =
+            std::fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+        let config: Self =
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.clone(), e))?;
+
+        config
+            .validate()
+            .map_err(|(field, reason)| ConfigError::Invalid(path, field, reason))?;
+
+        Ok(config)
+    }
+
+    /// Checks what the types alone don't, returning the offending field and
+    /// what's wrong with it
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        let claude = &self.claude;
+        let new_server = &self.new_server;
+
+        if chrono::NaiveDate::parse_from_str(&claude.anthropic_version, "%Y-%m-%d").is_err() {
+            return Err((
+                "claude.anthropic_version",
+                format!(
+                    "must be a date like 2023-06-01, not '{}'",
+                    claude.anthropic_version
+                ),
+            ));
+        }
+
+        if !(MIN_MESSAGE_CONTEXT_LENGTH..=MAX_MESSAGE_CONTEXT_LENGTH)
+            .contains(&claude.message_context_length)
+        {
+            return Err((
+                "claude.message_context_length",
+                format!(
+                    "must be between {MIN_MESSAGE_CONTEXT_LENGTH} and {MAX_MESSAGE_CONTEXT_LENGTH}, not {}",
+                    claude.message_context_length
+                ),
+            ));
+        }
+
+        if claude
+            .system_prompt
+            .as_ref()
+            .is_some_and(|p| p.trim().is_empty())
+        {
+            return Err(("claude.system_prompt", "must not be empty".to_string()));
+        }
+
+        let model = new_server.model.id();
+        if model.is_empty() || model.contains(char::is_whitespace) {
+            return Err((
+                "new_server.model",
+                format!("must be a model ID like claude-sonnet-4-0, not '{model}'"),
+            ));
+        }
+
+        if let Some(tz) = &new_server.timezone
+            && tz.parse::<chrono_tz::Tz>().is_err()
+        {
+            return Err((
+                "new_server.timezone",
+                format!("must be an IANA timezone like Europe/Berlin, not '{tz}'"),
+            ));
+        }
+
+        if let Some(l) = &new_server.locale
+            && l.parse::<chrono::Locale>().is_err()
+        {
+            return Err((
+                "new_server.locale",
+                format!("must be a locale like de_DE, not '{l}'"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigError};
+    use std::io::Write;
+
+    fn load(toml: &str) -> Result<Config, ConfigError> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(toml.as_bytes()).unwrap();
+        Config::load(file.path().to_path_buf())
+    }
+
+    #[test]
+    fn missing_settings_keep_defaults() {
+        let config = load(
+            r#"
+            [claude]
+            max_tokens = 4096
+
+            [new_server]
+            model = "claude-opus-4-6"
+            mention_policy = "Nobody"
+            user_rate_limit = { requests = 3, period_secs = 60 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.claude.max_tokens.get(), 4096);
+        assert_eq!(config.claude.message_context_length, 15);
+        assert_eq!(config.discord.channel_queue_size.get(), 128);
+
+        let record = config.new_server.record();
+        assert_eq!(record.model.id(), "claude-opus-4-6");
+        assert_eq!(record.user_rate_limit.unwrap().requests.get(), 3);
+        assert!(record.claude_api_key.is_none());
+    }
+
+    #[test]
+    fn errors_name_the_offending_setting() {
+        let error = load("[claude]\nmax_tokens = 0\n").unwrap_err().to_string();
+        assert!(error.contains("line 2"), "{error}");
+
+        let error = load("[claude]\nmax_token = 10\n").unwrap_err().to_string();
+        assert!(error.contains("unknown field `max_token`"), "{error}");
+
+        let error = load("[claude]\nmessage_context_length = 1\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("`claude.message_context_length`"), "{error}");
+
+        assert!(matches!(
+            load("[new_server]\ntimezone = \"Mars/Olympus\"\n"),
+            Err(ConfigError::Invalid(_, "new_server.timezone", _))
+        ));
+        assert!(matches!(
+            Config::load("/nonexistent.toml".into()),
+            Err(ConfigError::Read(..))
+        ));
+    }
+}
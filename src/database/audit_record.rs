@@ -0,0 +1,137 @@
+use bincode::{self, Decode, Encode};
+use redb::Value;
+use serde::{Deserialize, Serialize};
+
+use super::Record;
+use super::record::CONFIG_FIELDS;
+use super::schema::{self, SchemaError};
+
+/// The layout audit records are written in
+///
+/// 0. Unversioned, with every field
+/// 1. Every field, after the version marker
+const CURRENT_VERSION: u8 = 1;
+
+/// A change to one field of a server's config. Values are as they're shown by
+/// `/get_config`, so secrets are redacted.
+#[derive(Clone, Debug, Serialize, Deserialize, Decode, Encode, Default, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Unix timestamp of when the change was made
+    pub changed_at: i64,
+    /// The user who made the change, or 0 if it was made outside of Discord
+    pub changed_by: u64,
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl AuditRecord {
+    fn decode(data: &[u8]) -> Result<Self, SchemaError> {
+        match schema::split_version(data)? {
+            (version @ (0 | 1), fields) => schema::decode_exact(version, fields),
+            (version, _) => Err(SchemaError::UnknownVersion(version)),
+        }
+    }
+
+    /// The fields that differ between two configs, made by `changed_by` at
+    /// `changed_at`
+    pub fn diff(before: &Record, after: &Record, changed_by: u64, changed_at: i64) -> Vec<Self> {
+        CONFIG_FIELDS
+            .iter()
+            .filter(|field| !(field.same)(before, after))
+            .map(|field| AuditRecord {
+                changed_at,
+                changed_by,
+                field: field.name.to_string(),
+                old_value: (field.show)(before),
+                new_value: (field.show)(after),
+            })
+            .collect()
+    }
+}
+
+impl Value for AuditRecord {
+    type SelfType<'a>
+        = AuditRecord
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        AuditRecord::decode(data).unwrap_or_else(|e| {
+            log::error!("Couldn't decode audit record, ignoring it ({e})");
+            AuditRecord::default()
+        })
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        schema::encode_versioned(CURRENT_VERSION, value)
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("claude_discord_bot_audit_record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditRecord;
+    use crate::database::Record;
+
+    #[test]
+    fn diff_only_changed_fields_with_secrets_redacted() {
+        let before = Record::default();
+        let after = Record {
+            claude_api_key: Some("sk-ant-secret-1234".to_string()),
+            regenerate_on_edit: true,
+            ..Default::default()
+        };
+
+        let changes = AuditRecord::diff(&before, &after, 7, 100);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].field, "Claude API key");
+        assert!(changes[0].new_value.ends_with("1234"));
+        assert!(!changes[0].new_value.contains("secret"));
+        assert_eq!(changes[1].field, "Regenerate on edit");
+        assert_eq!(changes[1].old_value, "No");
+        assert_eq!(changes[1].new_value, "Yes");
+        assert!(
+            changes
+                .iter()
+                .all(|c| c.changed_by == 7 && c.changed_at == 100)
+        );
+    }
+
+    #[test]
+    fn diff_catches_changes_hidden_by_redaction() {
+        let before = Record {
+            claude_api_key: Some("sk-ant-first-1234".to_string()),
+            ..Default::default()
+        };
+        let after = Record {
+            claude_api_key: Some("sk-ant-other-1234".to_string()),
+            ..Default::default()
+        };
+
+        let changes = AuditRecord::diff(&before, &after, 7, 100);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "Claude API key");
+        assert_eq!(changes[0].old_value, changes[0].new_value);
+    }
+}
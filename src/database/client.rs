@@ -1,16 +1,51 @@
 #![allow(clippy::result_large_err)]
 
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::{num::NonZeroU64, path::PathBuf};
 
 use crate::claude::Model;
+use crate::discord::{
+    Access, DeletedTriggerAction, ManagerPermission, MentionPolicy, RateLimit, RateLimitScope,
+};
 
+use super::audit_record::AuditRecord;
+use super::feedback_record::{FeedbackRecord, Vote};
+use super::master_key::MasterKey;
 use super::record::Record;
+use super::response_record::ResponseRecord;
+use super::schema::{self, Raw, RawRecord, SchemaError};
+use itertools::Itertools;
 use thiserror::Error;
+use tokio::sync::broadcast;
 
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::{Database, ReadableTable, TableDefinition, Value};
 
 const TABLE: TableDefinition<u64, Record> = TableDefinition::new("claude_discord_bot");
+/// The same table as `TABLE`, without decoding its records
+const RAW_TABLE: TableDefinition<u64, RawRecord> = TableDefinition::new("claude_discord_bot");
+/// Configs that couldn't be decoded, keyed by server id and when they were
+/// quarantined
+const QUARANTINE_TABLE: TableDefinition<(u64, i64), &[u8]> =
+    TableDefinition::new("claude_discord_bot_quarantine");
+const RESPONSES_TABLE: TableDefinition<u64, ResponseRecord> =
+    TableDefinition::new("claude_discord_bot_responses");
+const RAW_RESPONSES_TABLE: TableDefinition<u64, Raw<ResponseRecord>> =
+    TableDefinition::new("claude_discord_bot_responses");
+const FEEDBACK_TABLE: TableDefinition<u64, FeedbackRecord> =
+    TableDefinition::new("claude_discord_bot_feedback");
+const RAW_FEEDBACK_TABLE: TableDefinition<u64, Raw<FeedbackRecord>> =
+    TableDefinition::new("claude_discord_bot_feedback");
+/// Keyed by server id, then the order the changes were made in
+const AUDIT_TABLE: TableDefinition<(u64, u64), AuditRecord> =
+    TableDefinition::new("claude_discord_bot_audit_log");
+/// Tokens used with the operator's default API key, keyed by UTC day (days
+/// since the Unix epoch)
+const DEFAULT_KEY_USAGE_TABLE: TableDefinition<i64, u64> =
+    TableDefinition::new("claude_discord_bot_default_key_usage");
+
+/// How many config changes can be waiting for subscribers to receive them
+const AUDIT_EVENT_BUFFER: usize = 64;
 
 #[derive(Debug, Error)]
 pub enum DatabaseClientError {
@@ -31,11 +66,34 @@ pub enum DatabaseClientError {
 
     #[error("Couldn't commit transaction ({0})")]
     Commit(redb::CommitError),
+
+    #[error("Claude API keys are encrypted, but no master key was given (--master-key-file)")]
+    MissingMasterKey,
+
+    #[error(
+        "The master key must be 32 base64 encoded bytes (generate one with `openssl rand -base64 32`)"
+    )]
+    InvalidMasterKey,
+
+    #[error("Couldn't encrypt the Claude API key of server {0}")]
+    Encryption(u64),
+
+    #[error(
+        "Couldn't decrypt the Claude API key of server {0}, was it encrypted with a different master key?"
+    )]
+    Decryption(u64),
+
+    #[error("Couldn't decode the record stored for id {0}, so it was left unchanged ({1})")]
+    Undecodable(u64, SchemaError),
 }
 
 #[derive(Clone)]
 pub struct Client {
     db: Arc<Database>,
+    audit_events: broadcast::Sender<(u64, AuditRecord)>,
+    master_key: Option<MasterKey>,
+    /// What servers without a stored config use
+    new_server_config: Record,
 }
 
 impl Client {
@@ -47,10 +105,287 @@ impl Client {
             let _table = write_txn
                 .open_table(TABLE)
                 .map_err(DatabaseClientError::TableOpen)?;
+            let _responses_table = write_txn
+                .open_table(RESPONSES_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+            let _feedback_table = write_txn
+                .open_table(FEEDBACK_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+            let _audit_table = write_txn
+                .open_table(AUDIT_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+            let _default_key_usage_table = write_txn
+                .open_table(DEFAULT_KEY_USAGE_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
         }
+        Self::migrate_configs(&write_txn)?;
         write_txn.commit().map_err(DatabaseClientError::Commit)?;
 
-        Ok(Self { db: Arc::new(db) })
+        let (audit_events, _) = broadcast::channel(AUDIT_EVENT_BUFFER);
+
+        Ok(Self {
+            db: Arc::new(db),
+            audit_events,
+            master_key: None,
+            new_server_config: Record::default(),
+        })
+    }
+
+    /// Rewrites configs stored in older layouts in the current one, and moves
+    /// any that can't be decoded to the quarantine table, so their servers
+    /// start over with the default config instead of breaking the bot
+    fn migrate_configs(write_txn: &redb::WriteTransaction) -> Result<(), DatabaseClientError> {
+        let mut table = write_txn
+            .open_table(RAW_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+        let mut quarantine = write_txn
+            .open_table(QUARANTINE_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        let configs = table
+            .iter()
+            .map_err(DatabaseClientError::Read)?
+            .map_ok(|(k, v)| (k.value(), v.value().to_vec()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DatabaseClientError::Read)?;
+
+        for (server_id, data) in configs {
+            match schema::decode(&data) {
+                Ok(_) if schema::version(&data).is_ok_and(|v| v == schema::CURRENT_VERSION) => {}
+                Ok(config) => {
+                    table
+                        .insert(server_id, schema::encode(&config).as_slice())
+                        .map_err(DatabaseClientError::Write)?;
+                }
+                Err(e) => {
+                    log::error!(
+                        "Quarantining undecodable config of server id {server_id}, it'll use the default config ({e})"
+                    );
+                    quarantine
+                        .insert((server_id, chrono::Utc::now().timestamp()), data.as_slice())
+                        .map_err(DatabaseClientError::Write)?;
+                    table
+                        .remove(server_id)
+                        .map_err(DatabaseClientError::Write)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encrypts Claude API keys with `master_key` before they're stored, and
+    /// decrypts them when they're read. Keys stored before they were encrypted
+    /// are encrypted right away.
+    pub fn with_master_key(self, master_key: MasterKey) -> Result<Self, DatabaseClientError> {
+        let client = Self {
+            master_key: Some(master_key),
+            ..self
+        };
+        client.encrypt_plaintext_api_keys()?;
+
+        Ok(client)
+    }
+
+    /// Encrypts the stored Claude API keys that aren't already
+    fn encrypt_plaintext_api_keys(&self) -> Result<(), DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        {
+            let mut table = write_txn
+                .open_table(TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            let plaintext = table
+                .iter()
+                .map_err(DatabaseClientError::Read)?
+                .map_ok(|(k, v)| (k.value(), v.value()))
+                .filter_ok(|(_, config)| {
+                    config
+                        .claude_api_key
+                        .as_deref()
+                        .is_some_and(|k| !MasterKey::is_encrypted(k))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DatabaseClientError::Read)?;
+
+            if !plaintext.is_empty() {
+                log::info!(
+                    "Encrypting {} Claude API key(s) stored before encryption was enabled",
+                    plaintext.len()
+                );
+            }
+
+            for (server_id, config) in plaintext {
+                table
+                    .insert(server_id, self.encrypt_api_key(server_id, config)?)
+                    .map_err(DatabaseClientError::Write)?;
+            }
+        }
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(())
+    }
+
+    /// Starts servers without a stored config off with `config`
+    pub fn with_new_server_config(self, config: Record) -> Self {
+        Self {
+            new_server_config: config,
+            ..self
+        }
+    }
+
+    fn master_key(&self) -> Result<&MasterKey, DatabaseClientError> {
+        self.master_key
+            .as_ref()
+            .ok_or(DatabaseClientError::MissingMasterKey)
+    }
+
+    fn decrypt_api_key(
+        &self,
+        server_id: u64,
+        mut config: Record,
+    ) -> Result<Record, DatabaseClientError> {
+        if let Some(stored) = config
+            .claude_api_key
+            .as_deref()
+            .filter(|k| MasterKey::is_encrypted(k))
+        {
+            config.claude_api_key = Some(self.master_key()?.decrypt(server_id, stored)?);
+        }
+
+        Ok(config)
+    }
+
+    fn encrypt_api_key(
+        &self,
+        server_id: u64,
+        mut config: Record,
+    ) -> Result<Record, DatabaseClientError> {
+        if let Some(api_key) = &config.claude_api_key {
+            config.claude_api_key = Some(self.master_key()?.encrypt(server_id, api_key)?);
+        }
+
+        Ok(config)
+    }
+
+    /// Checks that every stored Claude API key can be decrypted
+    pub fn verify_master_key(&self) -> Result<(), DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        for entry in table.iter().map_err(DatabaseClientError::Read)? {
+            let (server_id, config) = entry.map_err(DatabaseClientError::Read)?;
+            self.decrypt_api_key(server_id.value(), config.value())?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the configs table can be read
+    pub fn verify_readable(&self) -> Result<(), DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        table.first().map_err(DatabaseClientError::Read)?;
+
+        Ok(())
+    }
+
+    /// Re-encrypts every stored Claude API key with `new_master_key`,
+    /// including keys stored before they were encrypted, returning how many
+    /// there were
+    pub fn rotate_master_key(
+        &self,
+        new_master_key: &MasterKey,
+    ) -> Result<usize, DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        let rotated = {
+            let mut table = write_txn
+                .open_table(TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            let configs = table
+                .iter()
+                .map_err(DatabaseClientError::Read)?
+                .map_ok(|(k, v)| (k.value(), v.value()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DatabaseClientError::Read)?;
+
+            let mut rotated = 0;
+            for (server_id, config) in configs {
+                let mut config = self.decrypt_api_key(server_id, config)?;
+                let Some(api_key) = &config.claude_api_key else {
+                    continue;
+                };
+
+                config.claude_api_key = Some(new_master_key.encrypt(server_id, api_key)?);
+                table
+                    .insert(server_id, config)
+                    .map_err(DatabaseClientError::Write)?;
+                rotated += 1;
+            }
+
+            rotated
+        };
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(rotated)
+    }
+
+    /// Config changes as they're made, along with the id of the server they
+    /// were made in
+    pub fn subscribe_audit_log(&self) -> broadcast::Receiver<(u64, AuditRecord)> {
+        self.audit_events.subscribe()
+    }
+
+    /// A page of a server's config changes, newest first, along with how many
+    /// changes there are in total
+    pub fn get_audit_log(
+        &self,
+        server_id: u64,
+        skip: usize,
+        take: usize,
+    ) -> Result<(Vec<AuditRecord>, usize), DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(AUDIT_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        let range = || {
+            table
+                .range((server_id, 0)..=(server_id, u64::MAX))
+                .map_err(DatabaseClientError::Read)
+        };
+
+        let total = range()?.count();
+        let page = range()?
+            .rev()
+            .skip(skip)
+            .take(take)
+            .map_ok(|(_, v)| v.value())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DatabaseClientError::Read)?;
+
+        Ok((page, total))
     }
 
     pub fn get_config(&self, server_id: u64) -> Result<Record, DatabaseClientError> {
@@ -62,34 +397,217 @@ impl Client {
             .open_table(TABLE)
             .map_err(DatabaseClientError::TableOpen)?;
 
-        Ok(table
+        let config = table
             .get(server_id)
             .map_err(DatabaseClientError::Read)?
-            .map_or(Record::default(), |a| a.value()))
+            .map_or_else(|| self.new_server_config.clone(), |a| a.value());
+
+        self.decrypt_api_key(server_id, config)
+    }
+
+    /// Every server's stored config, with API keys left encrypted
+    pub fn get_stored_configs(&self) -> Result<Vec<(u64, Record)>, DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        table
+            .iter()
+            .map_err(DatabaseClientError::Read)?
+            .map_ok(|(k, v)| (k.value(), v.value()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DatabaseClientError::Read)
+    }
+
+    /// Stores configs exported with `get_stored_configs`, replacing those of
+    /// the same servers. Their API keys must be encrypted with this client's
+    /// master key, if they're encrypted.
+    pub fn import_configs(
+        &self,
+        configs: Vec<(u64, Record)>,
+        changed_by: u64,
+    ) -> Result<(), DatabaseClientError> {
+        let imported = configs
+            .iter()
+            .map(|(server_id, config)| self.decrypt_api_key(*server_id, config.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        let mut changes = Vec::new();
+        {
+            let mut table = write_txn
+                .open_table(TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+            let now = chrono::Utc::now().timestamp();
+
+            for ((server_id, config), after) in configs.into_iter().zip(imported) {
+                let before = table
+                    .insert(server_id, config)
+                    .map_err(DatabaseClientError::Write)?
+                    .map_or_else(Record::default, |v| v.value());
+                let before = self.decrypt_api_key(server_id, before)?;
+
+                let server_changes = AuditRecord::diff(&before, &after, changed_by, now);
+                append_audit_records(&write_txn, server_id, &server_changes)?;
+                changes.push((server_id, server_changes));
+            }
+        }
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        for (server_id, changes) in changes {
+            self.broadcast_changes(server_id, changes);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a server's config, returning whether it had one
+    pub fn remove_config(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+    ) -> Result<bool, DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        let removed = {
+            let mut table = write_txn
+                .open_table(TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            table
+                .remove(server_id)
+                .map_err(DatabaseClientError::Write)?
+                .map(|v| v.value())
+        };
+        let changes = match removed {
+            Some(before) => {
+                let before = self.decrypt_api_key(server_id, before)?;
+                let changes = AuditRecord::diff(
+                    &before,
+                    &Record::default(),
+                    changed_by,
+                    chrono::Utc::now().timestamp(),
+                );
+                append_audit_records(&write_txn, server_id, &changes)?;
+                Some(changes)
+            }
+            None => None,
+        };
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        let Some(changes) = changes else {
+            return Ok(false);
+        };
+        self.broadcast_changes(server_id, changes);
+
+        Ok(true)
+    }
+
+    /// Replaces a server's whole config
+    pub fn set_config(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        config: Record,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            *rec = config;
+        })
     }
 
     pub fn set_claude_api_key(
         &self,
         server_id: u64,
+        changed_by: u64,
         api_key: &str,
     ) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.claude_api_key = Some(api_key.to_string());
         })
     }
 
-    pub fn set_model(&self, server_id: u64, model: Model) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+    pub fn set_model(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        model: Model,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.model = model;
         })
     }
 
+    pub fn set_mention_policy(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        policy: MentionPolicy,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.mention_policy = policy;
+        })
+    }
+
+    pub fn set_timezone(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        timezone: Option<String>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.timezone = timezone;
+        })
+    }
+
+    pub fn set_locale(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        locale: Option<String>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.locale = locale;
+        })
+    }
+
+    pub fn set_regenerate_on_edit(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        regenerate: bool,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.regenerate_on_edit = regenerate;
+        })
+    }
+
+    pub fn set_deleted_trigger_action(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        action: DeletedTriggerAction,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.deleted_trigger_action = action;
+        })
+    }
+
     pub fn set_random_interaction_denominator(
         &self,
         server_id: u64,
+        changed_by: u64,
         denominator: Option<NonZeroU64>,
     ) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.random_interaction_chance_denominator = denominator;
         })
     }
@@ -97,9 +615,10 @@ impl Client {
     pub fn add_active_channel_id(
         &self,
         server_id: u64,
+        changed_by: u64,
         channel_id: u64,
     ) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.active_channel_ids.insert(channel_id);
         })
     }
@@ -107,20 +626,328 @@ impl Client {
     pub fn remove_active_channel_id(
         &self,
         server_id: u64,
+        changed_by: u64,
         channel_id: u64,
     ) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.active_channel_ids.remove(&channel_id);
         })
     }
 
-    pub fn clear_active_channel_ids(&self, server_id: u64) -> Result<(), DatabaseClientError> {
-        self.modify_config(server_id, move |rec| {
+    pub fn clear_active_channel_ids(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
             rec.active_channel_ids.clear();
         })
     }
 
-    fn modify_config<F>(&self, server_id: u64, update_config: F) -> Result<(), DatabaseClientError>
+    pub fn set_rate_limit(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        scope: RateLimitScope,
+        limit: Option<RateLimit>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| match scope {
+            RateLimitScope::User => rec.user_rate_limit = limit,
+            RateLimitScope::Channel => rec.channel_rate_limit = limit,
+            RateLimitScope::Guild => rec.guild_rate_limit = limit,
+        })
+    }
+
+    pub fn add_rate_limit_exempt_role_id(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        role_id: u64,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.rate_limit_exempt_role_ids.insert(role_id);
+        })
+    }
+
+    pub fn remove_rate_limit_exempt_role_id(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        role_id: u64,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.rate_limit_exempt_role_ids.remove(&role_id);
+        })
+    }
+
+    /// Puts a user on the allow or deny list, or takes them off both
+    pub fn set_user_access(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        user_id: u64,
+        access: Option<Access>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            set_access(
+                &mut rec.allowed_user_ids,
+                &mut rec.denied_user_ids,
+                user_id,
+                access,
+            );
+        })
+    }
+
+    /// Puts a role on the allow or deny list, or takes it off both
+    pub fn set_role_access(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        role_id: u64,
+        access: Option<Access>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            set_access(
+                &mut rec.allowed_role_ids,
+                &mut rec.denied_role_ids,
+                role_id,
+                access,
+            );
+        })
+    }
+
+    pub fn set_bot_manager_role_id(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        role_id: Option<u64>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.bot_manager_role_id = role_id;
+        })
+    }
+
+    pub fn set_bot_manager_permission(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        permission: ManagerPermission,
+        granted: bool,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            if granted {
+                rec.bot_manager_permissions.insert(permission);
+            } else {
+                rec.bot_manager_permissions.remove(&permission);
+            }
+        })
+    }
+
+    pub fn set_mod_log_channel_id(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        channel_id: Option<u64>,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_config(server_id, changed_by, move |rec| {
+            rec.mod_log_channel_id = channel_id;
+        })
+    }
+
+    pub fn get_responses(
+        &self,
+        trigger_message_id: u64,
+    ) -> Result<Option<ResponseRecord>, DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(RESPONSES_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        Ok(table
+            .get(trigger_message_id)
+            .map_err(DatabaseClientError::Read)?
+            .map(|v| v.value()))
+    }
+
+    /// Records more responses to a triggering message, keeping any that were
+    /// already recorded
+    pub fn add_responses(
+        &self,
+        trigger_message_id: u64,
+        responses: ResponseRecord,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_responses(trigger_message_id, move |rec| {
+            rec.channel_id = responses.channel_id;
+            rec.trigger_author_id = responses.trigger_author_id;
+            rec.truncated = responses.truncated;
+            rec.message_ids.extend(responses.message_ids);
+        })
+    }
+
+    /// Replaces the recorded responses to a triggering message
+    pub fn set_responses(
+        &self,
+        trigger_message_id: u64,
+        responses: ResponseRecord,
+    ) -> Result<(), DatabaseClientError> {
+        self.modify_responses(trigger_message_id, move |rec| {
+            *rec = responses;
+        })
+    }
+
+    pub fn take_responses(
+        &self,
+        trigger_message_id: u64,
+    ) -> Result<Option<ResponseRecord>, DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        let responses = {
+            let mut table = write_txn
+                .open_table(RESPONSES_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            table
+                .remove(trigger_message_id)
+                .map_err(DatabaseClientError::Write)?
+                .map(|v| v.value())
+        };
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(responses)
+    }
+
+    /// Starts tracking feedback on one of Claude's messages, replacing any
+    /// feedback previously given on it
+    pub fn add_feedback_target(&self, record: FeedbackRecord) -> Result<(), DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        {
+            let mut table = write_txn
+                .open_table(FEEDBACK_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            table
+                .insert(record.message_id, record)
+                .map_err(DatabaseClientError::Write)?;
+        }
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(())
+    }
+
+    /// Adds or removes a vote on one of Claude's messages, returning whether
+    /// the message's feedback is tracked
+    pub fn set_feedback_vote(
+        &self,
+        message_id: u64,
+        user_id: u64,
+        vote: Vote,
+        added: bool,
+    ) -> Result<bool, DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        let tracked = {
+            let record = read_for_update(
+                &write_txn,
+                RAW_FEEDBACK_TABLE,
+                message_id,
+                FeedbackRecord::decode,
+            )?;
+
+            if let Some(mut record) = record {
+                record.set_vote(user_id, vote, added);
+                write_txn
+                    .open_table(FEEDBACK_TABLE)
+                    .map_err(DatabaseClientError::TableOpen)?
+                    .insert(message_id, record)
+                    .map_err(DatabaseClientError::Write)?;
+                true
+            } else {
+                false
+            }
+        };
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(tracked)
+    }
+
+    /// All tracked feedback, optionally only for one server
+    pub fn get_feedback(
+        &self,
+        server_id: Option<u64>,
+    ) -> Result<Vec<FeedbackRecord>, DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(FEEDBACK_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        table
+            .iter()
+            .map_err(DatabaseClientError::Read)?
+            .map(|entry| entry.map(|(_, v)| v.value()))
+            .filter_ok(|record| server_id.is_none_or(|id| record.guild_id == id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DatabaseClientError::Read)
+    }
+
+    /// How many tokens were used with the default API key on `day`
+    pub fn get_default_key_usage(&self, day: i64) -> Result<u64, DatabaseClientError> {
+        let read_txn = self
+            .db
+            .begin_read()
+            .map_err(DatabaseClientError::Transaction)?;
+        let table = read_txn
+            .open_table(DEFAULT_KEY_USAGE_TABLE)
+            .map_err(DatabaseClientError::TableOpen)?;
+
+        Ok(table
+            .get(day)
+            .map_err(DatabaseClientError::Read)?
+            .map_or(0, |v| v.value()))
+    }
+
+    pub fn add_default_key_usage(&self, day: i64, tokens: u64) -> Result<(), DatabaseClientError> {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        {
+            let mut table = write_txn
+                .open_table(DEFAULT_KEY_USAGE_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
+
+            let used = table
+                .get(day)
+                .map_err(DatabaseClientError::Read)?
+                .map_or(0, |v| v.value());
+
+            table
+                .insert(day, used.saturating_add(tokens))
+                .map_err(DatabaseClientError::Write)?;
+        }
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        Ok(())
+    }
+
+    fn modify_config<F>(
+        &self,
+        server_id: u64,
+        changed_by: u64,
+        update_config: F,
+    ) -> Result<(), DatabaseClientError>
     where
         F: FnOnce(&mut Record),
     {
@@ -128,19 +955,69 @@ impl Client {
             .db
             .begin_write()
             .map_err(DatabaseClientError::Transaction)?;
-        {
+        let changes = {
+            let config = read_for_update(&write_txn, RAW_TABLE, server_id, schema::decode)?
+                .unwrap_or_else(|| self.new_server_config.clone());
+            let mut config = self.decrypt_api_key(server_id, config)?;
+            let before = config.clone();
+            update_config(&mut config);
+
+            let changes =
+                AuditRecord::diff(&before, &config, changed_by, chrono::Utc::now().timestamp());
+
             let mut table = write_txn
                 .open_table(TABLE)
                 .map_err(DatabaseClientError::TableOpen)?;
+            table
+                .insert(server_id, self.encrypt_api_key(server_id, config)?)
+                .map_err(DatabaseClientError::Write)?;
 
-            let mut config = table
-                .get(server_id)
-                .map_err(DatabaseClientError::Read)?
-                .map_or(Record::default(), |v| v.value());
-            update_config(&mut config);
+            append_audit_records(&write_txn, server_id, &changes)?;
+
+            changes
+        };
+        write_txn.commit().map_err(DatabaseClientError::Commit)?;
+
+        self.broadcast_changes(server_id, changes);
+
+        Ok(())
+    }
+
+    /// Sends a server's committed config changes to audit log subscribers
+    fn broadcast_changes(&self, server_id: u64, changes: Vec<AuditRecord>) {
+        for change in changes {
+            // Nobody may be listening
+            let _ = self.audit_events.send((server_id, change));
+        }
+    }
+
+    fn modify_responses<F>(
+        &self,
+        trigger_message_id: u64,
+        update_responses: F,
+    ) -> Result<(), DatabaseClientError>
+    where
+        F: FnOnce(&mut ResponseRecord),
+    {
+        let write_txn = self
+            .db
+            .begin_write()
+            .map_err(DatabaseClientError::Transaction)?;
+        {
+            let mut responses = read_for_update(
+                &write_txn,
+                RAW_RESPONSES_TABLE,
+                trigger_message_id,
+                ResponseRecord::decode,
+            )?
+            .unwrap_or_default();
+            update_responses(&mut responses);
 
+            let mut table = write_txn
+                .open_table(RESPONSES_TABLE)
+                .map_err(DatabaseClientError::TableOpen)?;
             table
-                .insert(server_id, config)
+                .insert(trigger_message_id, responses)
                 .map_err(DatabaseClientError::Write)?;
         }
         write_txn.commit().map_err(DatabaseClientError::Commit)?;
@@ -148,3 +1025,313 @@ impl Client {
         Ok(())
     }
 }
+
+/// Adds a server's config changes to its audit log, after those already in it
+fn append_audit_records(
+    write_txn: &redb::WriteTransaction,
+    server_id: u64,
+    changes: &[AuditRecord],
+) -> Result<(), DatabaseClientError> {
+    let mut audit_table = write_txn
+        .open_table(AUDIT_TABLE)
+        .map_err(DatabaseClientError::TableOpen)?;
+
+    let first_id = audit_table
+        .range((server_id, 0)..=(server_id, u64::MAX))
+        .map_err(DatabaseClientError::Read)?
+        .next_back()
+        .transpose()
+        .map_err(DatabaseClientError::Read)?
+        .map_or(0, |(k, _)| k.value().1 + 1);
+
+    for (id, change) in (first_id..).zip(changes) {
+        audit_table
+            .insert((server_id, id), change)
+            .map_err(DatabaseClientError::Write)?;
+    }
+
+    Ok(())
+}
+
+/// Decodes the record stored under `id` so it can be changed. Unlike reading
+/// it from its table, this errors if it can't be decoded, rather than
+/// defaulting it, so the stored bytes aren't written over.
+fn read_for_update<T: Value + 'static>(
+    write_txn: &redb::WriteTransaction,
+    table: TableDefinition<u64, Raw<T>>,
+    id: u64,
+    decode: impl FnOnce(&[u8]) -> Result<T, SchemaError>,
+) -> Result<Option<T>, DatabaseClientError> {
+    let table = write_txn
+        .open_table(table)
+        .map_err(DatabaseClientError::TableOpen)?;
+
+    table
+        .get(id)
+        .map_err(DatabaseClientError::Read)?
+        .map(|data| decode(data.value()))
+        .transpose()
+        .map_err(|e| DatabaseClientError::Undecodable(id, e))
+}
+
+fn set_access(
+    allowed: &mut HashSet<u64>,
+    denied: &mut HashSet<u64>,
+    id: u64,
+    access: Option<Access>,
+) {
+    allowed.remove(&id);
+    denied.remove(&id);
+
+    match access {
+        Some(Access::Allow) => allowed.insert(id),
+        Some(Access::Deny) => denied.insert(id),
+        None => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Client, DatabaseClientError, QUARANTINE_TABLE, RAW_RESPONSES_TABLE, RAW_TABLE, Record,
+        ResponseRecord, TABLE,
+    };
+    use crate::claude::Model;
+    use crate::database::MasterKey;
+    use crate::database::schema;
+    use redb::ReadableTable;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const NEW_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+    fn stored_api_key(db: &Client, server_id: u64) -> Option<String> {
+        let read_txn = db.db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        table.get(server_id).unwrap()?.value().claude_api_key
+    }
+
+    #[test]
+    fn api_keys_encrypted_at_rest() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_path_buf();
+        let db = Client::new(&path)
+            .unwrap()
+            .with_master_key(MasterKey::new(KEY).unwrap())
+            .unwrap();
+
+        db.set_claude_api_key(1, 10, "sk-ant-secret").unwrap();
+
+        assert!(!stored_api_key(&db, 1).unwrap().contains("secret"));
+        assert_eq!(
+            db.get_config(1).unwrap().claude_api_key.as_deref(),
+            Some("sk-ant-secret")
+        );
+
+        let new_key = MasterKey::new(NEW_KEY).unwrap();
+        assert_eq!(db.rotate_master_key(&new_key).unwrap(), 1);
+        assert!(matches!(
+            db.get_config(1),
+            Err(DatabaseClientError::Decryption(1))
+        ));
+
+        drop(db);
+        let db = Client::new(&path).unwrap();
+        assert!(matches!(
+            db.get_config(1),
+            Err(DatabaseClientError::MissingMasterKey)
+        ));
+        assert!(db.get_config(2).is_ok());
+
+        let db = db.with_master_key(new_key).unwrap();
+        db.verify_master_key().unwrap();
+        assert_eq!(
+            db.get_config(1).unwrap().claude_api_key.as_deref(),
+            Some("sk-ant-secret")
+        );
+    }
+
+    #[test]
+    fn plaintext_api_keys_encrypted_on_open() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let db = Client::new(&file.path().to_path_buf()).unwrap();
+
+        {
+            let write_txn = db.db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(TABLE).unwrap();
+                let config = Record {
+                    claude_api_key: Some("sk-ant-secret".to_string()),
+                    ..Default::default()
+                };
+                table.insert(1, config).unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+
+        let db = db.with_master_key(MasterKey::new(KEY).unwrap()).unwrap();
+
+        assert!(MasterKey::is_encrypted(&stored_api_key(&db, 1).unwrap()));
+        assert_eq!(
+            db.get_config(1).unwrap().claude_api_key.as_deref(),
+            Some("sk-ant-secret")
+        );
+    }
+
+    #[test]
+    fn old_configs_migrated_and_undecodable_ones_quarantined() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_path_buf();
+
+        {
+            let db = Client::new(&path).unwrap();
+            let write_txn = db.db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(RAW_TABLE).unwrap();
+                // Unversioned, from before the mention policy was added
+                table
+                    .insert(1, [1, 3, 107, 101, 121, 1, 5, 2, 1, 7].as_slice())
+                    .unwrap();
+                table.insert(2, [255, 99, 0].as_slice()).unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+
+        let db = Client::new(&path).unwrap();
+        assert_eq!(
+            db.get_config(1).unwrap().claude_api_key.as_deref(),
+            Some("key")
+        );
+        assert!(db.get_config(2).unwrap().claude_api_key.is_none());
+
+        let read_txn = db.db.begin_read().unwrap();
+        let table = read_txn.open_table(RAW_TABLE).unwrap();
+        let migrated = table.get(1).unwrap().unwrap();
+        assert_eq!(
+            schema::version(migrated.value()).unwrap(),
+            schema::CURRENT_VERSION
+        );
+        assert!(table.get(2).unwrap().is_none());
+
+        let quarantine = read_txn.open_table(QUARANTINE_TABLE).unwrap();
+        let (key, data) = quarantine.first().unwrap().unwrap();
+        assert_eq!(key.value().0, 2);
+        assert_eq!(data.value(), [255, 99, 0]);
+    }
+
+    #[test]
+    fn undecodable_records_not_written_over() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let db = Client::new(&file.path().to_path_buf()).unwrap();
+        // From a newer version of the bot, written after the configs were
+        // migrated
+        let newer = [255, 99, 0];
+
+        let write_txn = db.db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(RAW_TABLE).unwrap();
+            table.insert(1, newer.as_slice()).unwrap();
+            let mut responses = write_txn.open_table(RAW_RESPONSES_TABLE).unwrap();
+            responses.insert(2, newer.as_slice()).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        assert!(matches!(
+            db.set_model(1, 3, Model::new("claude-opus-4-5")),
+            Err(DatabaseClientError::Undecodable(1, _))
+        ));
+        assert!(matches!(
+            db.add_responses(2, ResponseRecord::default()),
+            Err(DatabaseClientError::Undecodable(2, _))
+        ));
+
+        let read_txn = db.db.begin_read().unwrap();
+        let table = read_txn.open_table(RAW_TABLE).unwrap();
+        assert_eq!(table.get(1).unwrap().unwrap().value(), newer);
+        let responses = read_txn.open_table(RAW_RESPONSES_TABLE).unwrap();
+        assert_eq!(responses.get(2).unwrap().unwrap().value(), newer);
+        assert_eq!(db.get_audit_log(1, 0, 10).unwrap().1, 0);
+    }
+
+    #[test]
+    fn config_changes_are_audited_newest_first() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let db = Client::new(&file.path().to_path_buf())
+            .unwrap()
+            .with_master_key(MasterKey::new(KEY).unwrap())
+            .unwrap();
+        let mut changes = db.subscribe_audit_log();
+
+        db.set_regenerate_on_edit(1, 10, true).unwrap();
+        db.set_claude_api_key(1, 11, "sk-ant-secret-1234").unwrap();
+        db.set_regenerate_on_edit(1, 10, true).unwrap();
+        db.set_regenerate_on_edit(2, 10, true).unwrap();
+
+        let (page, total) = db.get_audit_log(1, 0, 10).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(page[0].field, "Claude API key");
+        assert_eq!(page[0].changed_by, 11);
+        assert!(!page[0].new_value.contains("secret"));
+        assert_eq!(page[1].field, "Regenerate on edit");
+
+        let (page, total) = db.get_audit_log(1, 1, 10).unwrap();
+        assert_eq!((page.len(), total), (1, 2));
+
+        assert_eq!(changes.try_recv().unwrap().0, 1);
+        assert_eq!(changes.try_recv().unwrap().0, 1);
+        assert_eq!(changes.try_recv().unwrap().0, 2);
+    }
+
+    #[test]
+    fn configs_exported_imported_and_removed() {
+        let source = tempfile::NamedTempFile::new().unwrap();
+        let source = Client::new(&source.path().to_path_buf())
+            .unwrap()
+            .with_master_key(MasterKey::new(KEY).unwrap())
+            .unwrap();
+        source.set_claude_api_key(1, 10, "sk-ant-secret").unwrap();
+        source.set_regenerate_on_edit(2, 10, true).unwrap();
+
+        let exported = source.get_stored_configs().unwrap();
+        assert_eq!(exported.len(), 2);
+
+        let target = tempfile::NamedTempFile::new().unwrap();
+        let target = Client::new(&target.path().to_path_buf()).unwrap();
+        assert!(matches!(
+            target.import_configs(exported.clone(), 0),
+            Err(DatabaseClientError::MissingMasterKey)
+        ));
+        assert!(target.get_stored_configs().unwrap().is_empty());
+
+        let target = target
+            .with_master_key(MasterKey::new(KEY).unwrap())
+            .unwrap();
+        let mut changes = target.subscribe_audit_log();
+        target.import_configs(exported, 0).unwrap();
+        assert_eq!(
+            target.get_config(1).unwrap().claude_api_key.as_deref(),
+            Some("sk-ant-secret")
+        );
+        assert!(target.get_config(2).unwrap().regenerate_on_edit);
+
+        let (page, total) = target.get_audit_log(1, 0, 10).unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(page[0].field, "Claude API key");
+        assert_eq!(page[0].changed_by, 0);
+        assert!(!page[0].new_value.contains("secret"));
+        let (page, _) = target.get_audit_log(2, 0, 10).unwrap();
+        assert_eq!(page[0].field, "Regenerate on edit");
+        assert_eq!(changes.try_recv().unwrap().0, 1);
+        assert_eq!(changes.try_recv().unwrap().0, 2);
+
+        assert!(target.remove_config(2, 0).unwrap());
+        assert!(!target.remove_config(2, 0).unwrap());
+        assert!(!target.get_config(2).unwrap().regenerate_on_edit);
+
+        let (page, total) = target.get_audit_log(2, 0, 10).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(page[0].field, "Regenerate on edit");
+        assert_eq!(page[0].changed_by, 0);
+        assert_eq!(changes.try_recv().unwrap().0, 2);
+        assert!(changes.try_recv().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use bincode::{self, Decode, Encode};
+use redb::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use super::schema::{self, SchemaError};
+
+/// The layout feedback records are written in
+///
+/// 0. Unversioned, with every field
+/// 1. Every field, after the version marker
+const CURRENT_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vote {
+    Up,
+    Down,
+}
+
+/// A message Claude sent, what produced it, and how people reacted to it
+#[derive(Debug, Serialize, Deserialize, Decode, Encode, Default)]
+pub struct FeedbackRecord {
+    pub message_id: u64,
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub trigger_message_id: u64,
+    pub trigger: String,
+    pub model: String,
+    pub system_prompt_version: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Unix timestamp of when the message was sent
+    pub created_at: i64,
+    #[serde(skip)]
+    pub upvoter_ids: BTreeSet<u64>,
+    #[serde(skip)]
+    pub downvoter_ids: BTreeSet<u64>,
+}
+
+/// A [`FeedbackRecord`] as it's exported, with vote counts in place of voters
+#[derive(Serialize)]
+struct FeedbackLine<'a> {
+    #[serde(flatten)]
+    record: &'a FeedbackRecord,
+    thumbs_up: usize,
+    thumbs_down: usize,
+}
+
+impl FeedbackRecord {
+    pub(super) fn decode(data: &[u8]) -> Result<Self, SchemaError> {
+        match schema::split_version(data)? {
+            (version @ (0 | 1), fields) => schema::decode_exact(version, fields),
+            (version, _) => Err(SchemaError::UnknownVersion(version)),
+        }
+    }
+
+    pub fn set_vote(&mut self, user_id: u64, vote: Vote, added: bool) {
+        let voters = match vote {
+            Vote::Up => &mut self.upvoter_ids,
+            Vote::Down => &mut self.downvoter_ids,
+        };
+
+        if added {
+            voters.insert(user_id);
+        } else {
+            voters.remove(&user_id);
+        }
+    }
+
+    /// One JSON object per line, for offline analysis
+    pub fn to_jsonl(records: &[FeedbackRecord]) -> Result<String, serde_json::Error> {
+        let mut jsonl = String::new();
+
+        for record in records {
+            jsonl.push_str(&serde_json::to_string(&FeedbackLine {
+                record,
+                thumbs_up: record.upvoter_ids.len(),
+                thumbs_down: record.downvoter_ids.len(),
+            })?);
+            jsonl.push('\n');
+        }
+
+        Ok(jsonl)
+    }
+}
+
+impl Value for FeedbackRecord {
+    type SelfType<'a>
+        = FeedbackRecord
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        FeedbackRecord::decode(data).unwrap_or_else(|e| {
+            log::error!("Couldn't decode feedback record, ignoring it ({e})");
+            FeedbackRecord::default()
+        })
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        schema::encode_versioned(CURRENT_VERSION, value)
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("claude_discord_bot_feedback_record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeedbackRecord, Vote};
+
+    #[test]
+    fn jsonl_has_vote_counts_not_voters() {
+        let mut record = FeedbackRecord {
+            message_id: 1,
+            model: "claude-sonnet-4-0".to_string(),
+            ..Default::default()
+        };
+        record.set_vote(10, Vote::Up, true);
+        record.set_vote(11, Vote::Up, true);
+        record.set_vote(11, Vote::Up, false);
+        record.set_vote(12, Vote::Down, true);
+
+        let jsonl = FeedbackRecord::to_jsonl(&[record]).unwrap();
+        let line: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+
+        assert_eq!(line["message_id"], 1);
+        assert_eq!(line["model"], "claude-sonnet-4-0");
+        assert_eq!(line["thumbs_up"], 1);
+        assert_eq!(line["thumbs_down"], 1);
+        assert!(line.get("upvoter_ids").is_none());
+        assert!(jsonl.ends_with('\n'));
+    }
+}
@@ -0,0 +1,115 @@
+#![allow(clippy::result_large_err)]
+
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::client::DatabaseClientError;
+
+/// Marks a stored API key as encrypted, rather than one saved before keys
+/// were encrypted
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// The key Claude API keys are encrypted with before they're stored
+#[derive(Clone)]
+pub struct MasterKey {
+    key: Arc<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl MasterKey {
+    /// A master key from 32 base64 encoded bytes, like those printed by
+    /// `openssl rand -base64 32`
+    pub fn new(encoded: &str) -> Result<Self, DatabaseClientError> {
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|_| DatabaseClientError::InvalidMasterKey)?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| DatabaseClientError::InvalidMasterKey)?;
+
+        Ok(Self {
+            key: Arc::new(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn is_encrypted(stored: &str) -> bool {
+        stored.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    /// Encrypts `api_key` so it can only be decrypted for `server_id`
+    pub fn encrypt(&self, server_id: u64, api_key: &str) -> Result<String, DatabaseClientError> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| DatabaseClientError::Encryption(server_id))?;
+
+        let mut sealed = api_key.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(server_id.to_le_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| DatabaseClientError::Encryption(server_id))?;
+
+        Ok(format!(
+            "{ENCRYPTED_PREFIX}{}",
+            BASE64.encode([nonce.as_slice(), &sealed].concat())
+        ))
+    }
+
+    /// Decrypts an API key encrypted for `server_id`
+    pub fn decrypt(&self, server_id: u64, stored: &str) -> Result<String, DatabaseClientError> {
+        let sealed = stored
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .and_then(|s| BASE64.decode(s).ok())
+            .filter(|b| b.len() > NONCE_LEN)
+            .ok_or(DatabaseClientError::Decryption(server_id))?;
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| DatabaseClientError::Decryption(server_id))?;
+        let mut sealed = sealed.to_vec();
+        let api_key = self
+            .key
+            .open_in_place(nonce, Aad::from(server_id.to_le_bytes()), &mut sealed)
+            .map_err(|_| DatabaseClientError::Decryption(server_id))?;
+
+        String::from_utf8(api_key.to_vec()).map_err(|_| DatabaseClientError::Decryption(server_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MasterKey;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const OTHER_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+    #[test]
+    fn round_trip_only_with_same_key_and_server() {
+        let key = MasterKey::new(KEY).unwrap();
+        let encrypted = key.encrypt(1, "sk-ant-secret").unwrap();
+
+        assert!(MasterKey::is_encrypted(&encrypted));
+        assert!(!encrypted.contains("secret"));
+        assert_eq!(key.decrypt(1, &encrypted).unwrap(), "sk-ant-secret");
+        assert!(key.decrypt(2, &encrypted).is_err());
+        assert!(
+            MasterKey::new(OTHER_KEY)
+                .unwrap()
+                .decrypt(1, &encrypted)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_keys_of_wrong_length() {
+        assert!(MasterKey::new("c2hvcnQ=").is_err());
+        assert!(MasterKey::new("not base64!").is_err());
+    }
+}
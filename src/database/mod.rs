@@ -1,5 +1,14 @@
+mod audit_record;
 mod client;
+mod feedback_record;
+mod master_key;
 mod record;
+mod response_record;
+mod schema;
 
-pub use client::Client;
+pub use audit_record::AuditRecord;
+pub use client::{Client, DatabaseClientError};
+pub use feedback_record::{FeedbackRecord, Vote};
+pub use master_key::MasterKey;
 pub use record::Record;
+pub use response_record::ResponseRecord;
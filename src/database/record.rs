@@ -1,18 +1,41 @@
 use crate::claude::Model;
+use crate::discord::{
+    DeletedTriggerAction, LocalTime, ManagerPermission, MentionPolicy, RateLimit,
+};
 use bincode::{self, Decode, Encode};
 use itertools::Itertools;
 use redb::Value;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, fmt::Display, num::NonZeroU64};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::Display,
+    num::NonZeroU64,
+};
 
 use poise::serenity_prelude::{self as serenity, Mentionable};
 
-#[derive(Debug, Serialize, Deserialize, Decode, Encode, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Decode, Encode, Default)]
 pub struct Record {
     pub claude_api_key: Option<String>,
     pub random_interaction_chance_denominator: Option<NonZeroU64>,
     pub model: Model,
     pub active_channel_ids: HashSet<u64>,
+    pub mention_policy: MentionPolicy,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub regenerate_on_edit: bool,
+    pub deleted_trigger_action: DeletedTriggerAction,
+    pub user_rate_limit: Option<RateLimit>,
+    pub channel_rate_limit: Option<RateLimit>,
+    pub guild_rate_limit: Option<RateLimit>,
+    pub rate_limit_exempt_role_ids: HashSet<u64>,
+    pub allowed_user_ids: HashSet<u64>,
+    pub denied_user_ids: HashSet<u64>,
+    pub allowed_role_ids: HashSet<u64>,
+    pub denied_role_ids: HashSet<u64>,
+    pub bot_manager_role_id: Option<u64>,
+    pub bot_manager_permissions: BTreeSet<ManagerPermission>,
+    pub mod_log_channel_id: Option<u64>,
 }
 
 impl Record {
@@ -21,55 +44,186 @@ impl Record {
             .with_little_endian()
             .with_variable_int_encoding()
     }
+
+    pub fn local_time(&self) -> LocalTime {
+        LocalTime::new(self.timezone.as_deref(), self.locale.as_deref())
+    }
 }
 
-impl Display for Record {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let unset = String::from("**Not Set**");
+const UNSET: &str = "**Not Set**";
 
-        let claude_api_key = &self.claude_api_key.as_ref().map(|key| {
-            if key.len() <= 4 {
-                key.clone()
+/// A field of the config, as `/get_config` shows it and the audit log records
+/// changes to it
+pub(super) struct ConfigField {
+    pub(super) name: &'static str,
+    /// How the field is shown, with secrets redacted
+    pub(super) show: fn(&Record) -> String,
+    /// Whether two configs have the same value for the field
+    pub(super) same: fn(&Record, &Record) -> bool,
+}
+
+/// Every field of the config, in the order they're shown
+pub(super) const CONFIG_FIELDS: &[ConfigField] = &[
+    ConfigField {
+        name: "Claude API key",
+        show: |r| or_unset(r.claude_api_key.as_deref().map(redact)),
+        same: |a, b| a.claude_api_key == b.claude_api_key,
+    },
+    ConfigField {
+        name: "Interaction chance",
+        show: |r| {
+            or_unset(
+                r.random_interaction_chance_denominator
+                    .map(|denom| format!("1/{denom}")),
+            )
+        },
+        same: |a, b| {
+            a.random_interaction_chance_denominator == b.random_interaction_chance_denominator
+        },
+    },
+    ConfigField {
+        name: "Model",
+        show: |r| r.model.pretty_name(),
+        same: |a, b| a.model == b.model,
+    },
+    ConfigField {
+        name: "Mention policy",
+        show: |r| r.mention_policy.to_string(),
+        same: |a, b| a.mention_policy == b.mention_policy,
+    },
+    ConfigField {
+        name: "Timezone",
+        show: |r| or_unset(r.timezone.as_ref()),
+        same: |a, b| a.timezone == b.timezone,
+    },
+    ConfigField {
+        name: "Locale",
+        show: |r| or_unset(r.locale.as_ref()),
+        same: |a, b| a.locale == b.locale,
+    },
+    ConfigField {
+        name: "Regenerate on edit",
+        show: |r| if r.regenerate_on_edit { "Yes" } else { "No" }.to_string(),
+        same: |a, b| a.regenerate_on_edit == b.regenerate_on_edit,
+    },
+    ConfigField {
+        name: "On deleted trigger message",
+        show: |r| r.deleted_trigger_action.to_string(),
+        same: |a, b| a.deleted_trigger_action == b.deleted_trigger_action,
+    },
+    ConfigField {
+        name: "User rate limit",
+        show: |r| or_unset(r.user_rate_limit),
+        same: |a, b| a.user_rate_limit == b.user_rate_limit,
+    },
+    ConfigField {
+        name: "Channel rate limit",
+        show: |r| or_unset(r.channel_rate_limit),
+        same: |a, b| a.channel_rate_limit == b.channel_rate_limit,
+    },
+    ConfigField {
+        name: "Server rate limit",
+        show: |r| or_unset(r.guild_rate_limit),
+        same: |a, b| a.guild_rate_limit == b.guild_rate_limit,
+    },
+    ConfigField {
+        name: "Rate limit exempt roles",
+        show: |r| mention_list(&r.rate_limit_exempt_role_ids, serenity::RoleId::new),
+        same: |a, b| a.rate_limit_exempt_role_ids == b.rate_limit_exempt_role_ids,
+    },
+    ConfigField {
+        name: "Allowed users",
+        show: |r| mention_list(&r.allowed_user_ids, serenity::UserId::new),
+        same: |a, b| a.allowed_user_ids == b.allowed_user_ids,
+    },
+    ConfigField {
+        name: "Denied users",
+        show: |r| mention_list(&r.denied_user_ids, serenity::UserId::new),
+        same: |a, b| a.denied_user_ids == b.denied_user_ids,
+    },
+    ConfigField {
+        name: "Allowed roles",
+        show: |r| mention_list(&r.allowed_role_ids, serenity::RoleId::new),
+        same: |a, b| a.allowed_role_ids == b.allowed_role_ids,
+    },
+    ConfigField {
+        name: "Denied roles",
+        show: |r| mention_list(&r.denied_role_ids, serenity::RoleId::new),
+        same: |a, b| a.denied_role_ids == b.denied_role_ids,
+    },
+    ConfigField {
+        name: "Bot manager role",
+        show: |r| {
+            or_unset(
+                r.bot_manager_role_id
+                    .map(|id| serenity::RoleId::new(id).mention()),
+            )
+        },
+        same: |a, b| a.bot_manager_role_id == b.bot_manager_role_id,
+    },
+    ConfigField {
+        name: "Bot manager permissions",
+        show: |r| {
+            if r.bot_manager_permissions.is_empty() {
+                UNSET.to_string()
             } else {
-                "\\*".repeat(key.len() - 4) + &key[key.len() - 4..]
+                r.bot_manager_permissions.iter().join(", ")
             }
-        });
+        },
+        same: |a, b| a.bot_manager_permissions == b.bot_manager_permissions,
+    },
+    ConfigField {
+        name: "Mod log channel",
+        show: |r| {
+            or_unset(
+                r.mod_log_channel_id
+                    .map(|id| serenity::ChannelId::new(id).mention()),
+            )
+        },
+        same: |a, b| a.mod_log_channel_id == b.mod_log_channel_id,
+    },
+    ConfigField {
+        name: "Active channels",
+        show: |r| mention_list(&r.active_channel_ids, serenity::ChannelId::new),
+        same: |a, b| a.active_channel_ids == b.active_channel_ids,
+    },
+];
+
+fn or_unset(value: Option<impl Display>) -> String {
+    value.map_or_else(|| UNSET.to_string(), |v| v.to_string())
+}
 
-        let interaction_chance = self
-            .random_interaction_chance_denominator
-            .map(|denom| format!("1/{denom}"));
+/// Hides all but the last 4 characters of `key`
+fn redact(key: &str) -> String {
+    if key.len() <= 4 {
+        key.to_string()
+    } else {
+        "\\*".repeat(key.len() - 4) + &key[key.len() - 4..]
+    }
+}
 
-        let active_channels = format!(
+/// Mentions of every id in `ids`, in order
+fn mention_list<M: Mentionable>(ids: &HashSet<u64>, from_id: fn(u64) -> M) -> String {
+    if ids.is_empty() {
+        UNSET.to_string()
+    } else {
+        format!(
             "[ {} ]",
-            self.active_channel_ids
-                .clone()
-                .iter()
-                .map(|&id| serenity::ChannelId::new(id))
-                .map(|c| c.mention())
+            ids.iter()
+                .sorted()
+                .map(|&id| from_id(id).mention())
                 .join(", ")
-        );
-
-        let lines = vec![
-            format!(
-                "Claude API key: {}",
-                claude_api_key.clone().unwrap_or(unset.clone())
-            ),
-            format!(
-                "Interaction chance: {}",
-                interaction_chance.unwrap_or(unset.clone())
-            ),
-            format!("Model: {}", self.model.pretty_name()),
-            format!(
-                "Active channels: {}",
-                if self.active_channel_ids.is_empty() {
-                    unset.clone()
-                } else {
-                    active_channels
-                }
-            ),
-        ];
-
-        f.write_str(lines.into_iter().join("\n").as_str())
+        )
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut lines = CONFIG_FIELDS
+            .iter()
+            .map(|field| format!("{}: {}", field.name, (field.show)(self)));
+
+        f.write_str(&lines.join("\n"))
     }
 }
 
@@ -92,16 +246,19 @@ impl Value for Record {
     where
         Self: 'a,
     {
-        bincode::decode_from_slice(data, Record::get_encoding_config())
-            .unwrap()
-            .0
+        // Undecodable records are quarantined when the database is opened, so
+        // this should only happen if one was written by a newer bot since
+        super::schema::decode(data).unwrap_or_else(|e| {
+            log::error!("Couldn't decode server config, using the default one ({e})");
+            Record::default()
+        })
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
         Self: 'b,
     {
-        bincode::encode_to_vec(value, Record::get_encoding_config()).unwrap()
+        super::schema::encode(value)
     }
 
     fn type_name() -> redb::TypeName {
@@ -0,0 +1,102 @@
+use bincode::{self, Decode, Encode};
+use redb::Value;
+use serde::{Deserialize, Serialize};
+
+use super::schema::{self, Fields, SchemaError};
+
+/// The layout response records are written in
+///
+/// 0. Unversioned, either with only `channel_id` and `message_ids`, or with
+///    every field
+/// 1. Every field, after the version marker
+const CURRENT_VERSION: u8 = 1;
+
+/// The messages the bot sent in response to a triggering message, along with
+/// what's needed to regenerate or continue them
+#[derive(Debug, Serialize, Deserialize, Decode, Encode, Default)]
+pub struct ResponseRecord {
+    pub channel_id: u64,
+    pub message_ids: Vec<u64>,
+    pub trigger_author_id: u64,
+    /// Whether the last response hit the max amount of tokens
+    pub truncated: bool,
+}
+
+impl ResponseRecord {
+    pub(super) fn decode(data: &[u8]) -> Result<Self, SchemaError> {
+        match schema::split_version(data)? {
+            (0, fields) => {
+                let mut fields = Fields::new(0, fields);
+                let record = Self {
+                    channel_id: fields.next()?,
+                    message_ids: fields.next()?,
+                    trigger_author_id: fields.next()?,
+                    truncated: fields.next()?,
+                };
+                fields.finish(record)
+            }
+            (1, fields) => schema::decode_exact(1, fields),
+            (version, _) => Err(SchemaError::UnknownVersion(version)),
+        }
+    }
+}
+
+impl Value for ResponseRecord {
+    type SelfType<'a>
+        = ResponseRecord
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        ResponseRecord::decode(data).unwrap_or_else(|e| {
+            log::error!("Couldn't decode response record, ignoring it ({e})");
+            ResponseRecord::default()
+        })
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        schema::encode_versioned(CURRENT_VERSION, value)
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("claude_discord_bot_response_record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseRecord;
+    use redb::Value;
+
+    #[test]
+    fn unversioned_records_decode() {
+        // Written before the trigger author and truncation were tracked
+        let record = ResponseRecord::from_bytes(&[7, 2, 8, 9]);
+        assert_eq!(record.channel_id, 7);
+        assert_eq!(record.message_ids, [8, 9]);
+        assert_eq!(record.trigger_author_id, 0);
+
+        let record = ResponseRecord::from_bytes(&[7, 1, 8, 5, 1]);
+        assert_eq!(record.trigger_author_id, 5);
+        assert!(record.truncated);
+
+        let encoded = ResponseRecord::as_bytes(&record);
+        assert_eq!(encoded[..2], [0xff, 1]);
+        assert_eq!(ResponseRecord::from_bytes(&encoded).message_ids, [8]);
+    }
+}
@@ -0,0 +1,383 @@
+//! How records are laid out in the database, and how older layouts are
+//! migrated to the current one
+
+use std::marker::PhantomData;
+
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode};
+use redb::Value;
+use thiserror::Error;
+
+use super::record::Record;
+use crate::claude::Model;
+
+/// Starts every versioned record, followed by its version. Unversioned
+/// records start with the tag of their optional API key instead, 0 or 1.
+const VERSION_MARKER: u8 = 0xff;
+
+/// The layout records are written in
+///
+/// 0. Unversioned, with fields only ever appended, so any prefix of version
+///    1's fields (at least the first four)
+/// 1. All of `Record`'s fields, as of the mod log channel
+/// 2. The model stored by its ID, rather than as one of a fixed set
+pub const CURRENT_VERSION: u8 = 2;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Record is empty")]
+    Empty,
+
+    #[error("Record has version {0}, which is newer than this bot knows about")]
+    UnknownVersion(u8),
+
+    #[error("Couldn't decode version {0} record ({1})")]
+    Decode(u8, DecodeError),
+
+    #[error("Version {0} record has {1} bytes left over")]
+    TrailingBytes(u8, usize),
+}
+
+/// The version `data` was written in
+pub fn version(data: &[u8]) -> Result<u8, SchemaError> {
+    match data {
+        [] | [VERSION_MARKER] => Err(SchemaError::Empty),
+        [VERSION_MARKER, version, ..] => Ok(*version),
+        _ => Ok(0),
+    }
+}
+
+/// The version `data` was written in, and its fields
+pub fn split_version(data: &[u8]) -> Result<(u8, &[u8]), SchemaError> {
+    match version(data)? {
+        0 => Ok((0, data)),
+        version => Ok((version, &data[2..])),
+    }
+}
+
+/// Writes `value`'s fields after the version marker and `version`
+pub fn encode_versioned<T: Encode>(version: u8, value: &T) -> Vec<u8> {
+    let mut data = vec![VERSION_MARKER, version];
+    data.extend(bincode::encode_to_vec(value, Record::get_encoding_config()).unwrap());
+    data
+}
+
+pub fn encode(record: &Record) -> Vec<u8> {
+    encode_versioned(CURRENT_VERSION, record)
+}
+
+/// Decodes a record written in any known version, migrating it to the
+/// current layout
+pub fn decode(data: &[u8]) -> Result<Record, SchemaError> {
+    match split_version(data)? {
+        (version @ (0 | 1), fields) => decode_legacy(version, fields),
+        (2, fields) => decode_exact(2, fields),
+        (version, _) => Err(SchemaError::UnknownVersion(version)),
+    }
+}
+
+/// Decodes fields laid out exactly as `T`'s are
+pub fn decode_exact<T: Decode<()>>(version: u8, payload: &[u8]) -> Result<T, SchemaError> {
+    let (value, len) = bincode::decode_from_slice(payload, Record::get_encoding_config())
+        .map_err(|e| SchemaError::Decode(version, e))?;
+
+    match payload.len() - len {
+        0 => Ok(value),
+        left_over => Err(SchemaError::TrailingBytes(version, left_over)),
+    }
+}
+
+/// The models the bot supported before they were stored by ID, in the order
+/// they were encoded in
+#[derive(Decode, Default)]
+enum LegacyModel {
+    Opus46,
+    Sonnet46,
+    Opus45,
+    Sonnet45,
+    Haiku45,
+    Opus41,
+    Opus4,
+    #[default]
+    Sonnet4,
+}
+
+impl From<LegacyModel> for Model {
+    fn from(model: LegacyModel) -> Self {
+        Model::new(match model {
+            LegacyModel::Opus46 => "claude-opus-4-6",
+            LegacyModel::Sonnet46 => "claude-sonnet-4-6",
+            LegacyModel::Opus45 => "claude-opus-4-5",
+            LegacyModel::Sonnet45 => "claude-sonnet-4-5",
+            LegacyModel::Haiku45 => "claude-haiku-4-5",
+            LegacyModel::Opus41 => "claude-opus-4-1",
+            LegacyModel::Opus4 => "claude-opus-4-0",
+            LegacyModel::Sonnet4 => "claude-sonnet-4-0",
+        })
+    }
+}
+
+/// The fields of a record, decoded one at a time. Version 0's are defaulted
+/// once they run out, since fields were only ever appended to it.
+pub struct Fields<'a> {
+    version: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(version: u8, data: &'a [u8]) -> Self {
+        Self { version, data }
+    }
+
+    pub fn next<T: Decode<()> + Default>(&mut self) -> Result<T, SchemaError> {
+        if self.version == 0 && self.data.is_empty() {
+            return Ok(T::default());
+        }
+
+        let (value, len) = bincode::decode_from_slice(self.data, Record::get_encoding_config())
+            .map_err(|e| SchemaError::Decode(self.version, e))?;
+        self.data = &self.data[len..];
+
+        Ok(value)
+    }
+
+    /// `record`, if every field was decoded
+    pub fn finish<T>(self, record: T) -> Result<T, SchemaError> {
+        match self.data.len() {
+            0 => Ok(record),
+            left_over => Err(SchemaError::TrailingBytes(self.version, left_over)),
+        }
+    }
+}
+
+fn decode_legacy(version: u8, data: &[u8]) -> Result<Record, SchemaError> {
+    let mut fields = Fields::new(version, data);
+
+    let record = Record {
+        claude_api_key: fields.next()?,
+        random_interaction_chance_denominator: fields.next()?,
+        model: fields.next::<LegacyModel>()?.into(),
+        active_channel_ids: fields.next()?,
+        mention_policy: fields.next()?,
+        timezone: fields.next()?,
+        locale: fields.next()?,
+        regenerate_on_edit: fields.next()?,
+        deleted_trigger_action: fields.next()?,
+        user_rate_limit: fields.next()?,
+        channel_rate_limit: fields.next()?,
+        guild_rate_limit: fields.next()?,
+        rate_limit_exempt_role_ids: fields.next()?,
+        allowed_user_ids: fields.next()?,
+        denied_user_ids: fields.next()?,
+        allowed_role_ids: fields.next()?,
+        denied_role_ids: fields.next()?,
+        bot_manager_role_id: fields.next()?,
+        bot_manager_permissions: fields.next()?,
+        mod_log_channel_id: fields.next()?,
+    };
+
+    fields.finish(record)
+}
+
+/// The undecoded bytes of a `T`, for migrating or quarantining it, or for
+/// checking it can be decoded before it's written over
+#[derive(Debug)]
+pub struct Raw<T>(PhantomData<T>);
+
+pub type RawRecord = Raw<Record>;
+
+impl<T: Value> Value for Raw<T> {
+    type SelfType<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        value
+    }
+
+    fn type_name() -> redb::TypeName {
+        T::type_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CURRENT_VERSION, SchemaError, decode, encode, version};
+    use crate::claude::Model;
+    use crate::database::Record;
+    use crate::discord::{DeletedTriggerAction, ManagerPermission, MentionPolicy, RateLimit};
+    use std::num::{NonZeroU32, NonZeroU64};
+
+    // Unversioned records, as written before each field was appended
+    const V0_BASELINE: &[u8] = &[1, 3, 107, 101, 121, 1, 5, 2, 1, 7];
+    const V0_MENTION_POLICY: &[u8] = &[1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3];
+    const V0_LOCAL_TIME: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69,
+    ];
+    const V0_EDITS: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2,
+    ];
+    const V0_RATE_LIMITS: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14, 1,
+        8,
+    ];
+    const V0_ACCESS: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14, 1,
+        8, 1, 9, 0, 0, 1, 10,
+    ];
+    const V0_BOT_MANAGER: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14, 1,
+        8, 1, 9, 0, 0, 1, 10, 1, 11, 2, 1, 2,
+    ];
+    const V0_MOD_LOG: &[u8] = &[
+        1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101,
+        114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14, 1,
+        8, 1, 9, 0, 0, 1, 10, 1, 11, 2, 1, 2, 1, 12,
+    ];
+    const V1: &[u8] = &[
+        255, 1, 1, 3, 107, 101, 121, 1, 5, 2, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66,
+        101, 114, 108, 105, 110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14,
+        1, 8, 1, 9, 0, 0, 1, 10, 1, 11, 2, 1, 2, 1, 12,
+    ];
+
+    const V2: &[u8] = &[
+        255, 2, 1, 3, 107, 101, 121, 1, 5, 15, 99, 108, 97, 117, 100, 101, 45, 111, 112, 117, 115,
+        45, 52, 45, 53, 1, 7, 3, 1, 13, 69, 117, 114, 111, 112, 101, 47, 66, 101, 114, 108, 105,
+        110, 1, 5, 100, 101, 95, 68, 69, 1, 2, 1, 3, 60, 0, 1, 10, 251, 16, 14, 1, 8, 1, 9, 0, 0,
+        1, 10, 1, 11, 2, 1, 2, 1, 12,
+    ];
+
+    fn rate_limit(requests: u32, period_secs: u32) -> RateLimit {
+        RateLimit {
+            requests: NonZeroU32::new(requests).unwrap(),
+            period_secs: NonZeroU32::new(period_secs).unwrap(),
+        }
+    }
+
+    /// What each fixture holds, in the order the fields were added
+    fn fixtures() -> Vec<(&'static [u8], Record)> {
+        let baseline = Record {
+            claude_api_key: Some("key".to_string()),
+            random_interaction_chance_denominator: NonZeroU64::new(5),
+            model: Model::new("claude-opus-4-5"),
+            active_channel_ids: [7].into(),
+            ..Default::default()
+        };
+        let mention_policy = Record {
+            mention_policy: MentionPolicy::Everyone,
+            ..baseline.clone()
+        };
+        let local_time = Record {
+            timezone: Some("Europe/Berlin".to_string()),
+            locale: Some("de_DE".to_string()),
+            ..mention_policy.clone()
+        };
+        let edits = Record {
+            regenerate_on_edit: true,
+            deleted_trigger_action: DeletedTriggerAction::Delete,
+            ..local_time.clone()
+        };
+        let rate_limits = Record {
+            user_rate_limit: Some(rate_limit(3, 60)),
+            guild_rate_limit: Some(rate_limit(10, 3600)),
+            rate_limit_exempt_role_ids: [8].into(),
+            ..edits.clone()
+        };
+        let access = Record {
+            allowed_user_ids: [9].into(),
+            denied_role_ids: [10].into(),
+            ..rate_limits.clone()
+        };
+        let bot_manager = Record {
+            bot_manager_role_id: Some(11),
+            bot_manager_permissions: [ManagerPermission::Model, ManagerPermission::Channels].into(),
+            ..access.clone()
+        };
+        let mod_log = Record {
+            mod_log_channel_id: Some(12),
+            ..bot_manager.clone()
+        };
+
+        vec![
+            (V0_BASELINE, baseline),
+            (V0_MENTION_POLICY, mention_policy),
+            (V0_LOCAL_TIME, local_time),
+            (V0_EDITS, edits),
+            (V0_RATE_LIMITS, rate_limits),
+            (V0_ACCESS, access),
+            (V0_BOT_MANAGER, bot_manager),
+            (V0_MOD_LOG, mod_log.clone()),
+            (V1, mod_log.clone()),
+            (V2, mod_log),
+        ]
+    }
+
+    #[test]
+    fn every_version_decodes() {
+        for (data, expected) in fixtures() {
+            let record = decode(data).unwrap();
+            assert_eq!(record.to_string(), expected.to_string());
+            assert_eq!(record.model.id(), expected.model.id());
+        }
+    }
+
+    #[test]
+    fn encodes_current_version() {
+        let (_, record) = fixtures().pop().unwrap();
+
+        assert_eq!(encode(&record), V2);
+        assert_eq!(version(&encode(&record)).unwrap(), CURRENT_VERSION);
+        assert_eq!(version(V0_MOD_LOG).unwrap(), 0);
+    }
+
+    #[test]
+    fn undecodable_records_are_errors() {
+        assert!(matches!(decode(&[]), Err(SchemaError::Empty)));
+        assert!(matches!(
+            decode(&[255, 99, 0]),
+            Err(SchemaError::UnknownVersion(99))
+        ));
+        assert!(matches!(
+            decode(&V1[..V1.len() - 1]),
+            Err(SchemaError::Decode(1, _))
+        ));
+        assert!(matches!(
+            decode(&V2[..V2.len() - 1]),
+            Err(SchemaError::Decode(2, _))
+        ));
+        assert!(matches!(
+            decode(&[V0_MOD_LOG, &[0]].concat()),
+            Err(SchemaError::TrailingBytes(0, 1))
+        ));
+        // Model variant that doesn't exist
+        assert!(matches!(
+            decode(&[1, 3, 107, 101, 121, 1, 5, 99, 1, 7]),
+            Err(SchemaError::Decode(0, _))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+
+use crate::database::Record;
+
+/// Which list a user or role is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ChoiceParameter)]
+pub enum Access {
+    #[name = "Allow"]
+    Allow,
+    #[name = "Deny"]
+    Deny,
+}
+
+/// Whether any users or roles are allowed or denied
+pub fn is_restricted(server_config: &Record) -> bool {
+    !(server_config.allowed_user_ids.is_empty()
+        && server_config.denied_user_ids.is_empty()
+        && server_config.allowed_role_ids.is_empty()
+        && server_config.denied_role_ids.is_empty())
+}
+
+/// Whether a member with `role_ids` can ask Claude for something. Denials win
+/// over allowances, and once anyone is allowed, everyone else is denied.
+pub fn has_access(
+    server_config: &Record,
+    user_id: serenity::UserId,
+    role_ids: &[serenity::RoleId],
+) -> bool {
+    let user_id = user_id.get();
+    let has_role =
+        |ids: &std::collections::HashSet<u64>| role_ids.iter().any(|r| ids.contains(&r.get()));
+
+    if server_config.denied_user_ids.contains(&user_id) || has_role(&server_config.denied_role_ids)
+    {
+        return false;
+    }
+
+    let has_allowlist =
+        !(server_config.allowed_user_ids.is_empty() && server_config.allowed_role_ids.is_empty());
+
+    !has_allowlist
+        || server_config.allowed_user_ids.contains(&user_id)
+        || has_role(&server_config.allowed_role_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_access, is_restricted};
+    use crate::database::Record;
+    use poise::serenity_prelude as serenity;
+
+    fn user(id: u64) -> serenity::UserId {
+        serenity::UserId::new(id)
+    }
+
+    fn roles(ids: &[u64]) -> Vec<serenity::RoleId> {
+        ids.iter().map(|&id| serenity::RoleId::new(id)).collect()
+    }
+
+    #[test]
+    fn everyone_allowed_by_default() {
+        let cfg = Record::default();
+
+        assert!(!is_restricted(&cfg));
+        assert!(has_access(&cfg, user(1), &[]));
+    }
+
+    #[test]
+    fn denied_users_and_roles() {
+        let cfg = Record {
+            denied_user_ids: [1].into(),
+            denied_role_ids: [10].into(),
+            ..Default::default()
+        };
+
+        assert!(is_restricted(&cfg));
+        assert!(!has_access(&cfg, user(1), &[]));
+        assert!(!has_access(&cfg, user(2), &roles(&[10, 11])));
+        assert!(has_access(&cfg, user(2), &roles(&[11])));
+    }
+
+    #[test]
+    fn allowlist_excludes_everyone_else() {
+        let cfg = Record {
+            allowed_user_ids: [1].into(),
+            allowed_role_ids: [10].into(),
+            ..Default::default()
+        };
+
+        assert!(has_access(&cfg, user(1), &[]));
+        assert!(has_access(&cfg, user(2), &roles(&[10])));
+        assert!(!has_access(&cfg, user(2), &roles(&[11])));
+    }
+
+    #[test]
+    fn denial_wins_over_allowance() {
+        let cfg = Record {
+            allowed_role_ids: [10].into(),
+            denied_user_ids: [1].into(),
+            ..Default::default()
+        };
+
+        assert!(!has_access(&cfg, user(1), &roles(&[10])));
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use itertools::Itertools;
+
+use crate::claude::{Model, ModelInfo};
+use crate::discord::permission::can_manage_api_key;
+use crate::discord::{CommandError, PoiseApplicationContext};
+
+/// How long to wait for the API key to be entered
+const MODAL_TIMEOUT: Duration = Duration::from_mins(10);
+
+#[derive(Debug, poise::Modal)]
+#[name = "Set Claude API key"]
+struct ApiKeyModal {
+    #[name = "API key from the Anthropic console"]
+    #[placeholder = "sk-ant-..."]
+    api_key: String,
+}
+
+/// What to tell someone who set an API key that can use `models`, when the
+/// server is set to use `current`
+fn access_report(models: &[ModelInfo], current: &Model) -> String {
+    let mut report = format!(
+        "API key set. It can use {}.",
+        models.iter().map(|m| m.display_name.as_str()).join(", ")
+    );
+
+    if !current.is_among(models) {
+        write!(
+            report,
+            "\n-# It can't use {}, which this server is set to use. Change it with `/set_model`.",
+            current.pretty_name()
+        )
+        .unwrap();
+    }
+
+    report
+}
+
+/// Sets the Claude API key, after checking it with Anthropic
+#[poise::command(slash_command, guild_only, check = "can_manage_api_key")]
+pub async fn set_api_key(ctx: PoiseApplicationContext<'_>) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let Some(ApiKeyModal { api_key }) =
+        poise::execute_modal(ctx, None::<ApiKeyModal>, Some(MODAL_TIMEOUT)).await?
+    else {
+        return Ok(());
+    };
+    let api_key = api_key.trim();
+
+    let reply = match ctx.data().claude.models(api_key).await {
+        Ok(Some(models)) if !models.is_empty() => {
+            let config = ctx.data().db.get_config(guild_id.get())?;
+            ctx.data()
+                .db
+                .set_claude_api_key(guild_id.get(), ctx.author().id.get(), api_key)?;
+
+            access_report(&models, &config.model)
+        }
+        Ok(Some(_)) => "*That API key can't use any models, so it wasn't saved*".to_string(),
+        Ok(None) => "*Anthropic didn't accept that API key, so it wasn't saved*".to_string(),
+        Err(e) => {
+            log::warn!("Couldn't check API key ({e})");
+            "*Couldn't check that API key with Anthropic, so it wasn't saved. Try again later.*"
+                .to_string()
+        }
+    };
+
+    ctx.send(poise::CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::access_report;
+    use crate::claude::{Model, ModelInfo};
+
+    fn info(id: &str, display_name: &str) -> ModelInfo {
+        ModelInfo {
+            id: id.to_string(),
+            display_name: display_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn report_lists_models_and_warns_about_current() {
+        let models = [
+            info("claude-sonnet-4-6", "Claude Sonnet 4.6"),
+            info("claude-haiku-4-5-20251001", "Claude Haiku 4.5"),
+        ];
+
+        let report = access_report(&models, &Model::new("claude-haiku-4-5"));
+        assert_eq!(
+            report,
+            "API key set. It can use Claude Sonnet 4.6, Claude Haiku 4.5."
+        );
+
+        let report = access_report(&models, &Model::new("claude-opus-4-6"));
+        assert!(report.contains("can't use Opus 4.6"));
+    }
+}
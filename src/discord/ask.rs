@@ -0,0 +1,430 @@
+#![allow(clippy::result_large_err)]
+
+use std::time::Instant;
+
+use itertools::Itertools;
+use poise::serenity_prelude as serenity;
+
+use crate::claude;
+use crate::database::Record;
+use crate::discord::access;
+use crate::discord::error_reply::ErrorReply;
+use crate::discord::event_handlers::component::share_button;
+use crate::discord::event_handlers::feedback::{FeedbackSource, track_feedback};
+use crate::discord::message::split_message;
+use crate::discord::{CommandError, NormalizeContent, OutgoingMentions, PoiseContext};
+use crate::metrics;
+
+/// Leaves room for the footer added when an ephemeral response is shared
+const MESSAGE_LENGTH_LIMIT: usize = 1900;
+
+/// Sends an error reply only the invoker can see
+pub(super) async fn error_reply(
+    ctx: PoiseContext<'_>,
+    reply: ErrorReply,
+) -> Result<(), CommandError> {
+    metrics::record_error_reply(reply.kind());
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(reply.pretty_str())
+            .ephemeral(true),
+    )
+    .await
+    .inspect_err(|_| metrics::record_send_failure("interaction_response"))?;
+
+    Ok(())
+}
+
+/// Checks that the invoker can ask Claude for something, returning their
+/// roles if so
+pub(super) async fn check_access(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+) -> Result<Vec<serenity::RoleId>, ErrorReply> {
+    let role_ids = ctx
+        .author_member()
+        .await
+        .map(|m| m.roles.clone())
+        .unwrap_or_default();
+
+    if !access::has_access(config, ctx.author().id, &role_ids) {
+        return Err(ErrorReply::AccessDenied);
+    }
+
+    Ok(role_ids)
+}
+
+/// Uses up one of the invoker's requests, or fails if they're out of them
+pub(super) fn acquire_rate_limit(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    role_ids: &[serenity::RoleId],
+) -> Result<(), ErrorReply> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    ctx.data()
+        .rate_limiter
+        .acquire(
+            config,
+            guild_id,
+            ctx.channel_id(),
+            ctx.author().id,
+            role_ids,
+            Instant::now(),
+        )
+        .map_err(ErrorReply::rate_limited)
+}
+
+/// Checks that the invoker can ask Claude for something, using up one of
+/// their requests if so
+pub(super) async fn admit(ctx: PoiseContext<'_>, config: &Record) -> Result<(), ErrorReply> {
+    let role_ids = check_access(ctx, config).await?;
+    acquire_rate_limit(ctx, config, &role_ids)
+}
+
+/// The most recent messages in the channel the command was used in, oldest
+/// first
+async fn recent_history(ctx: PoiseContext<'_>) -> Result<Vec<serenity::Message>, CommandError> {
+    let length = ctx.data().claude.message_context_length();
+
+    Ok(ctx
+        .channel_id()
+        .messages(ctx, serenity::GetMessages::new().limit(length - 1))
+        .await?
+        .into_iter()
+        .rev()
+        .collect_vec())
+}
+
+/// `message` and the messages leading up to it, oldest first
+async fn history_until(
+    ctx: PoiseContext<'_>,
+    message: &serenity::Message,
+) -> Result<Vec<serenity::Message>, CommandError> {
+    let length = ctx.data().claude.message_context_length();
+
+    Ok(std::iter::once(message.clone())
+        .chain(
+            message
+                .channel_id
+                .messages(
+                    ctx,
+                    serenity::GetMessages::new()
+                        .before(message.id)
+                        .limit(length - 1),
+                )
+                .await?,
+        )
+        .rev()
+        .collect_vec())
+}
+
+/// What Claude said, without the reactions it can't make outside a channel
+pub(super) struct ClaudeReply {
+    pub texts: Vec<String>,
+    pub usage: claude::Usage,
+}
+
+/// Claude's reply to `msgs`, or the reason there isn't one
+pub(super) async fn request_reply(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    msgs: &[claude::Message],
+) -> Result<ClaudeReply, ErrorReply> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Err(ErrorReply::SomethingWentWrong);
+    };
+
+    let api_key =
+        match ctx
+            .data()
+            .default_api_key
+            .api_key_for(&ctx.data().db, guild_id.get(), config)
+        {
+            Ok(Some(api_key)) => api_key,
+            Ok(None) => return Err(ErrorReply::MissingAPIKey),
+            Err(e) => {
+                log::error!("Couldn't get the API key for server id {guild_id} ({e})");
+                return Err(ErrorReply::SomethingWentWrong);
+            }
+        };
+
+    let response = match ctx
+        .data()
+        .claude
+        .get_response(msgs, api_key.key, &config.model, &config.local_time().now())
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Error requesting response from Claude ({e})");
+            return Err(ErrorReply::SomethingWentWrong);
+        }
+    };
+
+    if let Err(e) = api_key.record_usage(&ctx.data().db, response.usage) {
+        log::error!("Couldn't record default API key usage ({e})");
+    }
+
+    match response.stop_reason {
+        claude::StopReason::Refusal => return Err(ErrorReply::TermsOfServiceViolation),
+        claude::StopReason::MaxTokens if response.content.is_empty() => {
+            return Err(ErrorReply::MaxTokens);
+        }
+        _ => (),
+    }
+
+    let mut texts = vec![];
+    let mut reactions = vec![];
+    for action in response.content {
+        match action {
+            claude::Action::SendMessage(txt) => texts.push(txt),
+            claude::Action::ReactToMessage(emoji) => reactions.push(emoji),
+            claude::Action::Pass => (),
+        }
+    }
+
+    // There's no message to react to, so a reaction-only response is sent as
+    // a message instead
+    if texts.is_empty() && !reactions.is_empty() {
+        texts.push(reactions.iter().join(" "));
+    }
+
+    if texts.is_empty() {
+        return Err(ErrorReply::NoResponse);
+    }
+
+    Ok(ClaudeReply {
+        texts,
+        usage: response.usage,
+    })
+}
+
+/// How a response to a command is shown
+#[derive(Clone, Copy)]
+pub(super) struct Output {
+    pub ephemeral: bool,
+    /// The message the response is about, which it replies to when an
+    /// ephemeral response is posted to the channel
+    pub reply_to: Option<serenity::MessageId>,
+}
+
+/// Sends `texts` as replies to the (deferred) interaction, returning the ids
+/// of the messages everyone can see
+pub(super) async fn send_texts(
+    ctx: PoiseContext<'_>,
+    texts: &[String],
+    mentions: &OutgoingMentions,
+    output: Output,
+) -> Result<Vec<serenity::MessageId>, CommandError> {
+    let mut sent = vec![];
+
+    for piece in texts
+        .iter()
+        .flat_map(|txt| split_message(&mentions.mentionify(txt), MESSAGE_LENGTH_LIMIT))
+    {
+        let mut reply = poise::CreateReply::default()
+            .content(piece)
+            .allowed_mentions(mentions.allowed_mentions())
+            .ephemeral(output.ephemeral);
+
+        if output.ephemeral {
+            reply = reply.components(vec![share_button(output.reply_to)]);
+        }
+
+        let handle = ctx
+            .send(reply)
+            .await
+            .inspect_err(|_| metrics::record_send_failure("interaction_response"))?;
+
+        if !output.ephemeral {
+            sent.push(handle.message().await?.id);
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Gets Claude's response to `msgs` and replies to the (deferred) interaction
+/// with it
+async fn reply_with_claude(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    msgs: &[claude::Message],
+    mentions: &OutgoingMentions,
+    output: Output,
+    trigger: &str,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    if let Err(reply) = admit(ctx, config).await {
+        return error_reply(ctx, reply).await;
+    }
+
+    let reply = match request_reply(ctx, config, msgs).await {
+        Ok(r) => r,
+        Err(reply) => return error_reply(ctx, reply).await,
+    };
+
+    let sent = send_texts(ctx, &reply.texts, mentions, output).await?;
+
+    track_feedback(
+        &ctx.data().db,
+        &ctx.data().claude,
+        &FeedbackSource {
+            guild_id,
+            channel_id: ctx.channel_id(),
+            trigger_message_id: output
+                .reply_to
+                .unwrap_or(serenity::MessageId::new(ctx.id())),
+            trigger,
+            model: &config.model,
+            usage: reply.usage,
+        },
+        &sent,
+    )?;
+
+    Ok(())
+}
+
+/// Asks Claude something
+#[poise::command(slash_command, guild_only)]
+pub async fn ask(
+    ctx: PoiseContext<'_>,
+    #[description = "What to ask Claude"] prompt: String,
+    #[description = "An image for Claude to look at"] image: Option<serenity::Attachment>,
+    #[description = "Whether Claude sees recent messages in this channel (default: no)"]
+    include_context: Option<bool>,
+    #[description = "Whether only you can see the response (default: no)"] ephemeral: Option<bool>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    if image.as_ref().is_some_and(|a| {
+        !a.content_type
+            .as_ref()
+            .is_some_and(|t| t.starts_with("image/"))
+    }) {
+        return error_reply(ctx, ErrorReply::NotAnImage).await;
+    }
+
+    let ephemeral = ephemeral.unwrap_or(false);
+    if ephemeral {
+        ctx.defer_ephemeral().await?;
+    } else {
+        ctx.defer().await?;
+    }
+
+    let config = ctx.data().db.get_config(guild_id.get())?;
+    let local_time = config.local_time();
+
+    let history = if include_context.unwrap_or(false) {
+        recent_history(ctx).await?
+    } else {
+        vec![]
+    };
+
+    let mut msgs = history
+        .iter()
+        .flat_map(|m| claude::Message::from(m, ctx.serenity_context(), local_time))
+        .collect_vec();
+
+    let author_name = ctx.author().display_name();
+    msgs.push(claude::Message::from_command(
+        author_name,
+        &local_time.format(*ctx.created_at(), "%-m-%-d-%Y %-I:%M%p"),
+        &prompt,
+        image.as_ref().map(|a| a.url.as_str()),
+    ));
+
+    let mentions = OutgoingMentions::new(&history, config.mention_policy.clone());
+    let output = Output {
+        ephemeral,
+        reply_to: None,
+    };
+
+    reply_with_claude(ctx, &config, &msgs, &mentions, output, "ask_command").await
+}
+
+/// Has Claude respond to a message, seeing the conversation leading up to it
+#[poise::command(context_menu_command = "Ask Claude about this", guild_only)]
+pub async fn ask_about(
+    ctx: PoiseContext<'_>,
+    #[description = "Message to ask Claude about"] message: serenity::Message,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let config = ctx.data().db.get_config(guild_id.get())?;
+    let local_time = config.local_time();
+
+    let history = history_until(ctx, &message).await?;
+    let mut msgs = history
+        .iter()
+        .flat_map(|m| claude::Message::from(m, ctx.serenity_context(), local_time))
+        .collect_vec();
+
+    msgs.push(claude::Message::user_text(&format!(
+        "*@{} is asking you about this message from @{}: '{}'*",
+        ctx.author().display_name(),
+        message.author.display_name(),
+        message.normalize_content(&ctx.serenity_context().cache, local_time),
+    )));
+
+    let mentions = OutgoingMentions::new(&history, config.mention_policy.clone());
+    let output = Output {
+        ephemeral: true,
+        reply_to: Some(message.id),
+    };
+
+    reply_with_claude(ctx, &config, &msgs, &mentions, output, "ask_about_menu").await
+}
+
+/// Has Claude translate a message into your language
+#[poise::command(context_menu_command = "Translate", guild_only)]
+pub async fn translate(
+    ctx: PoiseContext<'_>,
+    #[description = "Message to translate"] message: serenity::Message,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let config = ctx.data().db.get_config(guild_id.get())?;
+    let local_time = config.local_time();
+
+    let mut msgs =
+        claude::Message::from(&message, ctx.serenity_context(), local_time).collect_vec();
+
+    msgs.push(claude::Message::user_text(&format!(
+        "*@{} wants the message above translated into the language of the Discord locale \
+        '{}'. Respond with only the translation.*",
+        ctx.author().display_name(),
+        ctx.locale().unwrap_or("en-US"),
+    )));
+
+    let mentions = OutgoingMentions::new(
+        std::slice::from_ref(&message),
+        config.mention_policy.clone(),
+    );
+    let output = Output {
+        ephemeral: true,
+        reply_to: Some(message.id),
+    };
+
+    reply_with_claude(ctx, &config, &msgs, &mentions, output, "translate_menu").await
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use poise::serenity_prelude as serenity;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::database::{self, AuditRecord};
+use crate::metrics;
+
+/// A config change as it's shown in Discord
+pub fn format_change(change: &AuditRecord) -> String {
+    let changed_by = if change.changed_by == 0 {
+        String::from("The bot's operator")
+    } else {
+        format!("<@{}>", change.changed_by)
+    };
+
+    format!(
+        "<t:{}:f> {changed_by} changed **{}** from {} to {}",
+        change.changed_at, change.field, change.old_value, change.new_value
+    )
+}
+
+/// Posts config changes to each server's mod log channel as they're made
+pub fn mirror_to_mod_log(http: Arc<serenity::Http>, db: database::Client) {
+    let mut changes = db.subscribe_audit_log();
+
+    tokio::spawn(async move {
+        loop {
+            let (server_id, change) = match changes.recv().await {
+                Ok(c) => c,
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Missed {n} config changes while mirroring them to mod logs");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let channel_id = match db.get_config(server_id) {
+                Ok(config) => config.mod_log_channel_id,
+                Err(e) => {
+                    log::error!("Couldn't get config for server id {server_id} ({e})");
+                    continue;
+                }
+            };
+
+            let Some(channel_id) = channel_id.map(serenity::ChannelId::new) else {
+                continue;
+            };
+
+            let message = serenity::CreateMessage::new()
+                .content(format_change(&change))
+                .allowed_mentions(serenity::CreateAllowedMentions::new());
+
+            if let Err(e) = channel_id.send_message(&http, message).await {
+                metrics::record_send_failure("mod_log");
+                log::warn!("Couldn't post config change to mod log channel id {channel_id} ({e})");
+            }
+        }
+    });
+}
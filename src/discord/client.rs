@@ -1,17 +1,40 @@
-use crate::discord::MessageContext;
+use crate::config::DiscordConfig;
+use crate::discord::health::HealthCheck;
+use crate::discord::shutdown::{self, Shutdown};
+use crate::discord::{DefaultApiKey, MessageContext, RateLimiter, SerenityMessageContext};
+use crate::http::{self, HttpError, Reply};
+use crate::metrics;
 use dashmap::DashMap;
+use hyper::StatusCode;
 use poise::{PrefixFrameworkOptions, serenity_prelude as serenity};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc;
 
+pub(super) type ChannelSenders<CTX> = Arc<DashMap<serenity::ChannelId, mpsc::Sender<CTX>>>;
+
 pub struct CustomData<CTX: MessageContext> {
     pub db: crate::database::Client,
     pub claude: crate::claude::Client,
-    pub channel_senders: DashMap<serenity::ChannelId, mpsc::Sender<CTX>>,
+    pub channel_senders: ChannelSenders<CTX>,
+    pub rate_limiter: RateLimiter,
+    pub default_api_key: DefaultApiKey,
+    /// How many messages can wait for each channel's task
+    pub channel_queue_size: NonZeroUsize,
+    pub shutdown: Arc<Shutdown>,
 }
 
 pub struct Bot {
     client: serenity::Client,
+    channel_senders: ChannelSenders<SerenityMessageContext>,
+    shutdown: Arc<Shutdown>,
+    shutdown_timeout: Duration,
+    db: crate::database::Client,
+    commands_registered: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Error)]
@@ -28,10 +51,20 @@ impl Bot {
         discord_token: &str,
         database_client: crate::database::Client,
         claude_client: crate::claude::Client,
+        default_api_key: DefaultApiKey,
+        config: &DiscordConfig,
     ) -> Result<Bot, DiscordBotError> {
+        let channel_senders = ChannelSenders::default();
+        let shutdown = Arc::new(Shutdown::default());
+        let commands_registered = Arc::new(AtomicBool::new(false));
+        let channel_queue_size = config.channel_queue_size;
         let intents =
             serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::MESSAGE_CONTENT;
 
+        let data_channel_senders = channel_senders.clone();
+        let data_shutdown = shutdown.clone();
+        let setup_db = database_client.clone();
+        let setup_commands_registered = commands_registered.clone();
         let framework = poise::Framework::builder()
             .options(poise::FrameworkOptions {
                 event_handler: |ctx, event, framework, data| {
@@ -44,23 +77,49 @@ impl Bot {
                     ..Default::default()
                 },
                 commands: vec![
+                    super::ask::ask(),
+                    super::ask::ask_about(),
+                    super::ask::translate(),
+                    super::summary::summarize_channel(),
+                    super::summary::summarize_from_here(),
                     super::command::get_config(),
-                    super::command::set_api_key(),
+                    super::api_key::set_api_key(),
                     super::command::set_model(),
+                    super::command::set_mention_policy(),
+                    super::command::set_timezone(),
+                    super::command::set_locale(),
+                    super::command::set_regenerate_on_edit(),
+                    super::command::set_deleted_trigger_action(),
                     super::command::set_random_interaction_chance(),
+                    super::command::set_rate_limit(),
+                    super::command::add_rate_limit_exempt_role(),
+                    super::command::remove_rate_limit_exempt_role(),
+                    super::command::set_user_access(),
+                    super::command::set_role_access(),
                     super::command::add_active_channel(),
                     super::command::remove_active_channel(),
                     super::command::clear_active_channels(),
+                    super::command::export_feedback(),
+                    super::command::set_bot_manager_role(),
+                    super::command::set_bot_manager_permission(),
+                    super::command::set_mod_log_channel(),
+                    super::command::config_history(),
                 ],
                 ..Default::default()
             })
-            .setup(|ctx, _ready, framework| {
+            .setup(move |ctx, _ready, framework| {
                 Box::pin(async move {
                     poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                    setup_commands_registered.store(true, Ordering::Release);
+                    super::audit_log::mirror_to_mod_log(ctx.http.clone(), setup_db.clone());
                     Ok(CustomData {
-                        db: database_client,
+                        db: setup_db,
                         claude: claude_client,
-                        channel_senders: DashMap::new(),
+                        channel_senders: data_channel_senders,
+                        rate_limiter: RateLimiter::default(),
+                        default_api_key,
+                        channel_queue_size,
+                        shutdown: data_shutdown,
                     })
                 })
             })
@@ -71,10 +130,111 @@ impl Bot {
             .await
             .map_err(DiscordBotError::Creation)?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            channel_senders,
+            shutdown,
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout_secs),
+            db: database_client,
+            commands_registered,
+        })
+    }
+
+    /// Serves Prometheus metrics at `/metrics` on `address`
+    pub async fn serve_metrics(&self, address: SocketAddr) -> Result<(), HttpError> {
+        let channel_senders = self.channel_senders.clone();
+
+        http::serve(address, move |path| {
+            let channel_senders = channel_senders.clone();
+
+            async move {
+                (path == "/metrics").then(|| {
+                    metrics::set_channel_queues(
+                        channel_senders
+                            .iter()
+                            .filter(|entry| !entry.value().is_closed())
+                            .map(|entry| {
+                                let tx = entry.value();
+                                (entry.key().get(), tx.max_capacity() - tx.capacity())
+                            }),
+                    );
+
+                    Reply {
+                        status: StatusCode::OK,
+                        content_type: "text/plain; version=0.0.4",
+                        body: metrics::render(),
+                    }
+                })
+            }
+        })
+        .await
+    }
+
+    /// Serves `/healthz`, answering 200 while the database can be read, and
+    /// `/readyz`, answering 200 while the bot is also connected to Discord
+    /// and not shutting down. Both answer 503 otherwise, with a JSON
+    /// `HealthReport` either way.
+    pub async fn serve_health(&self, address: SocketAddr) -> Result<(), HttpError> {
+        let health_check = HealthCheck {
+            db: self.db.clone(),
+            shard_runners: self.client.shard_manager.runners.clone(),
+            channel_senders: self.channel_senders.clone(),
+            commands_registered: self.commands_registered.clone(),
+            shutdown: self.shutdown.clone(),
+        };
+
+        http::serve(address, move |path| {
+            let health_check = health_check.clone();
+
+            async move {
+                let ready = match path.as_str() {
+                    "/healthz" => false,
+                    "/readyz" => true,
+                    _ => return None,
+                };
+
+                let report = health_check.report().await;
+                let ok = if ready {
+                    report.is_ready()
+                } else {
+                    report.is_healthy()
+                };
+
+                Some(Reply {
+                    status: if ok {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    },
+                    content_type: "application/json",
+                    body: serde_json::to_string(&report).unwrap(),
+                })
+            }
+        })
+        .await
     }
 
+    /// Runs until SIGTERM or Ctrl-C, then stops taking new messages, gives the
+    /// channel tasks `shutdown_timeout` to handle the ones they have, and
+    /// disconnects from Discord
     pub async fn run(&mut self) -> Result<(), DiscordBotError> {
+        let shard_manager = self.client.shard_manager.clone();
+        let channel_senders = self.channel_senders.clone();
+        let shutdown = self.shutdown.clone();
+        let timeout = self.shutdown_timeout;
+
+        tokio::spawn(async move {
+            shutdown::signal().await;
+            log::info!("Shutting down, no longer accepting messages");
+
+            let aborted = shutdown.drain(|| channel_senders.clear(), timeout).await;
+            if aborted == 0 {
+                log::info!("Every channel task finished");
+            }
+
+            shard_manager.shutdown_all().await;
+        });
+
         self.client.start().await.map_err(DiscordBotError::Start)
     }
 }
@@ -1,9 +1,21 @@
-use std::num::NonZeroU64;
+use itertools::Itertools;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::sync::Arc;
 
+use poise::ChoiceParameter;
 use poise::serenity_prelude::{self as serenity, Mentionable};
 
-use crate::claude::Model;
-use crate::discord::{CommandError, PoiseContext};
+use crate::claude::{Model, ModelInfo};
+use crate::database::FeedbackRecord;
+use crate::discord::audit_log::format_change;
+use crate::discord::permission::{
+    can_manage_behavior, can_manage_channels, can_manage_feedback, can_manage_limits,
+    can_manage_model,
+};
+use crate::discord::{
+    Access, CommandError, DeletedTriggerAction, ManagerPermission, MentionPolicy, PoiseContext,
+    RateLimit, RateLimitScope,
+};
 
 /// Displays your server's config
 #[poise::command(slash_command)]
@@ -26,45 +38,255 @@ pub async fn get_config(ctx: PoiseContext<'_>) -> Result<(), CommandError> {
     Ok(())
 }
 
-/// Sets the Claude API key
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
-pub async fn set_api_key(
+/// The models the server's API key, or the default one, can use
+async fn server_models(
+    ctx: PoiseContext<'_>,
+    guild_id: serenity::GuildId,
+) -> Result<Result<Arc<Vec<ModelInfo>>, &'static str>, CommandError> {
+    let config = ctx.data().db.get_config(guild_id.get())?;
+
+    let Some(api_key) =
+        ctx.data()
+            .default_api_key
+            .api_key_for(&ctx.data().db, guild_id.get(), &config)?
+    else {
+        return Ok(Err("*Set an API key with `/set_api_key` first*"));
+    };
+
+    Ok(match ctx.data().claude.models(api_key.key).await {
+        Ok(Some(models)) => Ok(models),
+        Ok(None) => Err("*Anthropic doesn't accept this server's API key anymore*"),
+        Err(e) => {
+            log::warn!("Couldn't get models for server id {guild_id} ({e})");
+            Err("*Couldn't get the models this server's API key can use, try again later*")
+        }
+    })
+}
+
+async fn autocomplete_model(
+    ctx: PoiseContext<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+    let Ok(Ok(models)) = server_models(ctx, guild_id).await else {
+        return vec![];
+    };
+
+    let partial = partial.to_lowercase();
+
+    models
+        .iter()
+        .filter(|m| {
+            m.id.to_lowercase().contains(&partial)
+                || m.display_name.to_lowercase().contains(&partial)
+        })
+        .take(25)
+        .map(|m| serenity::AutocompleteChoice::new(m.display_name.clone(), m.id.clone()))
+        .collect()
+}
+
+/// Sets the Claude Model
+#[poise::command(slash_command, check = "can_manage_model")]
+pub async fn set_model(
     ctx: PoiseContext<'_>,
-    #[description = "API key from the Anthropic console"] api_key: String,
+    #[description = "Model ID or alias (e.g. claude-sonnet-4-0)"]
+    #[autocomplete = "autocomplete_model"]
+    model: String,
 ) -> Result<(), CommandError> {
     let Some(guild_id) = ctx.guild_id() else {
         ctx.say("Couldn't get server id").await?;
         return Ok(());
     };
 
-    ctx.data().db.set_claude_api_key(guild_id.get(), &api_key)?;
+    let models = match server_models(ctx, guild_id).await? {
+        Ok(models) => models,
+        Err(reply) => {
+            ctx.say(reply).await?;
+            return Ok(());
+        }
+    };
+
+    let model = Model::new(model.trim());
+    if !model.is_among(&models) {
+        ctx.say(format!("*This server's API key can't use '{model}'*"))
+            .await?;
+        return Ok(());
+    }
+
+    ctx.data()
+        .db
+        .set_model(guild_id.get(), ctx.author().id.get(), model.clone())?;
 
-    ctx.say("API key set").await?;
+    let name = models
+        .iter()
+        .find(|m| m.id == model.id())
+        .map_or_else(|| model.pretty_name(), |m| m.display_name.clone());
+
+    match model.capabilities() {
+        Some(capabilities) => {
+            ctx.say(format!("Model set to '{name}' ({capabilities})"))
+                .await?
+        }
+        None => ctx.say(format!("Model set to '{name}'")).await?,
+    };
 
     Ok(())
 }
 
-/// Sets the Claude Model
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
-pub async fn set_model(
+/// Sets who Claude's messages are allowed to ping
+#[poise::command(slash_command, check = "can_manage_behavior")]
+pub async fn set_mention_policy(
+    ctx: PoiseContext<'_>,
+    #[description = "Who Claude's messages may ping"] policy: MentionPolicy,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data()
+        .db
+        .set_mention_policy(guild_id.get(), ctx.author().id.get(), policy.clone())?;
+
+    ctx.say(format!("Mention policy set to '{policy}'")).await?;
+
+    Ok(())
+}
+
+// poise awaits autocomplete callbacks, so this has to be async
+#[allow(clippy::unused_async)]
+async fn autocomplete_timezone<'a>(
+    _ctx: PoiseContext<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+
+    chrono_tz::TZ_VARIANTS
+        .iter()
+        .map(|tz| tz.name())
+        .filter(move |name| name.to_lowercase().contains(&partial))
+        .take(25)
+        .map(String::from)
+}
+
+/// Sets the timezone used for message timestamps
+#[poise::command(slash_command, check = "can_manage_behavior")]
+pub async fn set_timezone(
+    ctx: PoiseContext<'_>,
+    #[description = "IANA timezone name (e.g. America/New_York). Leave empty to use the host's timezone."]
+    #[autocomplete = "autocomplete_timezone"]
+    timezone: Option<String>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    if let Some(tz) = &timezone
+        && tz.parse::<chrono_tz::Tz>().is_err()
+    {
+        ctx.say(format!("'{tz}' isn't a valid timezone")).await?;
+        return Ok(());
+    }
+
+    ctx.data()
+        .db
+        .set_timezone(guild_id.get(), ctx.author().id.get(), timezone.clone())?;
+
+    if let Some(tz) = timezone {
+        ctx.say(format!("Timezone set to '{tz}'")).await?;
+    } else {
+        ctx.say("Timezone reset to the host's timezone").await?;
+    }
+
+    Ok(())
+}
+
+/// Sets the locale used for message timestamps
+#[poise::command(slash_command, check = "can_manage_behavior")]
+pub async fn set_locale(
+    ctx: PoiseContext<'_>,
+    #[description = "POSIX locale name (e.g. en_US, de_DE). Leave empty to use en_US."]
+    locale: Option<String>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    if let Some(l) = &locale
+        && l.parse::<chrono::Locale>().is_err()
+    {
+        ctx.say(format!("'{l}' isn't a valid locale")).await?;
+        return Ok(());
+    }
+
+    ctx.data()
+        .db
+        .set_locale(guild_id.get(), ctx.author().id.get(), locale.clone())?;
+
+    if let Some(l) = locale {
+        ctx.say(format!("Locale set to '{l}'")).await?;
+    } else {
+        ctx.say("Locale reset to 'en_US'").await?;
+    }
+
+    Ok(())
+}
+
+/// Sets whether Claude regenerates its response when the triggering message is edited
+#[poise::command(slash_command, check = "can_manage_behavior")]
+pub async fn set_regenerate_on_edit(
     ctx: PoiseContext<'_>,
-    #[description = "Model name"] model: Model,
+    #[description = "Whether to regenerate responses to edited messages"] enabled: bool,
 ) -> Result<(), CommandError> {
     let Some(guild_id) = ctx.guild_id() else {
         ctx.say("Couldn't get server id").await?;
         return Ok(());
     };
 
-    ctx.data().db.set_model(guild_id.get(), model.clone())?;
+    ctx.data()
+        .db
+        .set_regenerate_on_edit(guild_id.get(), ctx.author().id.get(), enabled)?;
+
+    if enabled {
+        ctx.say("Enabled regenerating responses to edited messages")
+            .await?;
+    } else {
+        ctx.say("Disabled regenerating responses to edited messages")
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets what happens to Claude's response when the triggering message is deleted
+#[poise::command(slash_command, check = "can_manage_behavior")]
+pub async fn set_deleted_trigger_action(
+    ctx: PoiseContext<'_>,
+    #[description = "What to do with the response"] action: DeletedTriggerAction,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
 
-    ctx.say(format!("Model set to '{}'", model.pretty_name()))
+    ctx.data().db.set_deleted_trigger_action(
+        guild_id.get(),
+        ctx.author().id.get(),
+        action.clone(),
+    )?;
+
+    ctx.say(format!("Deleted trigger action set to '{action}'"))
         .await?;
 
     Ok(())
 }
 
 /// Sets the random interaction chance
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+#[poise::command(slash_command, check = "can_manage_behavior")]
 pub async fn set_random_interaction_chance(
     ctx: PoiseContext<'_>,
     #[description = "The `1/denominator` chance that Claude reacts on a per-message basis. Set to 0 to disable."]
@@ -77,9 +299,11 @@ pub async fn set_random_interaction_chance(
 
     let denominator = NonZeroU64::new(denominator);
 
-    ctx.data()
-        .db
-        .set_random_interaction_denominator(guild_id.get(), denominator)?;
+    ctx.data().db.set_random_interaction_denominator(
+        guild_id.get(),
+        ctx.author().id.get(),
+        denominator,
+    )?;
 
     if let Some(d) = denominator {
         ctx.say(format!("Interaction chance set to 1/{d} per message"))
@@ -91,8 +315,147 @@ pub async fn set_random_interaction_chance(
     Ok(())
 }
 
+/// Sets how often Claude can be asked for something
+#[poise::command(slash_command, check = "can_manage_limits")]
+pub async fn set_rate_limit(
+    ctx: PoiseContext<'_>,
+    #[description = "What the limit is counted against"] scope: RateLimitScope,
+    #[description = "How many requests are allowed per period. Set to 0 to disable."] requests: u32,
+    #[description = "The period in seconds (default: 60)"]
+    #[min = 1]
+    period_secs: Option<u32>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let limit = NonZeroU32::new(requests).map(|requests| RateLimit {
+        requests,
+        period_secs: period_secs
+            .and_then(NonZeroU32::new)
+            .unwrap_or(NonZeroU32::new(60).unwrap()),
+    });
+
+    ctx.data()
+        .db
+        .set_rate_limit(guild_id.get(), ctx.author().id.get(), scope, limit)?;
+
+    if let Some(limit) = limit {
+        ctx.say(format!("{} rate limit set to {limit}", scope.name()))
+            .await?;
+    } else {
+        ctx.say(format!(
+            "Disabled the {} rate limit",
+            scope.name().to_lowercase()
+        ))
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Exempts a role from rate limits
+#[poise::command(slash_command, check = "can_manage_limits")]
+pub async fn add_rate_limit_exempt_role(
+    ctx: PoiseContext<'_>,
+    #[description = "The role"] role: serenity::Role,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data().db.add_rate_limit_exempt_role_id(
+        guild_id.get(),
+        ctx.author().id.get(),
+        role.id.get(),
+    )?;
+
+    ctx.say(format!("'{}' is now exempt from rate limits", role.name))
+        .await?;
+
+    Ok(())
+}
+
+/// Stops exempting a role from rate limits
+#[poise::command(slash_command, check = "can_manage_limits")]
+pub async fn remove_rate_limit_exempt_role(
+    ctx: PoiseContext<'_>,
+    #[description = "The role"] role: serenity::Role,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data().db.remove_rate_limit_exempt_role_id(
+        guild_id.get(),
+        ctx.author().id.get(),
+        role.id.get(),
+    )?;
+
+    ctx.say(format!(
+        "'{}' is no longer exempt from rate limits",
+        role.name
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Allows or denies a user from asking Claude for things
+#[poise::command(slash_command, check = "can_manage_limits")]
+pub async fn set_user_access(
+    ctx: PoiseContext<'_>,
+    #[description = "The user"] user: serenity::User,
+    #[description = "Leave empty to take the user off both lists"] access: Option<Access>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data()
+        .db
+        .set_user_access(guild_id.get(), ctx.author().id.get(), user.id.get(), access)?;
+
+    ctx.say(access_set_reply(&user.name, access)).await?;
+
+    Ok(())
+}
+
+/// Allows or denies a role from asking Claude for things
+#[poise::command(slash_command, check = "can_manage_limits")]
+pub async fn set_role_access(
+    ctx: PoiseContext<'_>,
+    #[description = "The role"] role: serenity::Role,
+    #[description = "Leave empty to take the role off both lists"] access: Option<Access>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data()
+        .db
+        .set_role_access(guild_id.get(), ctx.author().id.get(), role.id.get(), access)?;
+
+    ctx.say(access_set_reply(&role.name, access)).await?;
+
+    Ok(())
+}
+
+fn access_set_reply(name: &str, access: Option<Access>) -> String {
+    match access {
+        Some(Access::Allow) => format!("Added '{name}' to the allow list"),
+        Some(Access::Deny) => format!("Added '{name}' to the deny list"),
+        None => format!("Removed '{name}' from the allow and deny lists"),
+    }
+}
+
 /// Add a channel to the set of Claude's active channels
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+#[poise::command(slash_command, check = "can_manage_channels")]
 pub async fn add_active_channel(
     ctx: PoiseContext<'_>,
     #[description = "The channel"]
@@ -108,7 +471,7 @@ pub async fn add_active_channel(
 
     ctx.data()
         .db
-        .add_active_channel_id(guild_id.get(), channel_id.get())?;
+        .add_active_channel_id(guild_id.get(), ctx.author().id.get(), channel_id.get())?;
 
     ctx.say(format!("Added {} to the set of active channels", {
         channel_id.mention()
@@ -119,7 +482,7 @@ pub async fn add_active_channel(
 }
 
 /// Remove a channel from the set of Claude's active channels
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+#[poise::command(slash_command, check = "can_manage_channels")]
 pub async fn remove_active_channel(
     ctx: PoiseContext<'_>,
     #[description = "The channel"]
@@ -133,9 +496,11 @@ pub async fn remove_active_channel(
 
     let channel_id = channel.id();
 
-    ctx.data()
-        .db
-        .remove_active_channel_id(guild_id.get(), channel_id.get())?;
+    ctx.data().db.remove_active_channel_id(
+        guild_id.get(),
+        ctx.author().id.get(),
+        channel_id.get(),
+    )?;
 
     ctx.say(format!("Removed {} from the set of active channels", {
         channel_id.mention()
@@ -145,17 +510,185 @@ pub async fn remove_active_channel(
     Ok(())
 }
 
+/// Exports feedback on Claude's messages in this server as JSONL
+#[poise::command(slash_command, check = "can_manage_feedback")]
+pub async fn export_feedback(ctx: PoiseContext<'_>) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let feedback = ctx.data().db.get_feedback(Some(guild_id.get()))?;
+
+    if feedback.is_empty() {
+        ctx.send(
+            poise::CreateReply::default()
+                .content("No feedback has been recorded yet")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let jsonl = FeedbackRecord::to_jsonl(&feedback)?;
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("Feedback on {} messages", feedback.len()))
+            .attachment(serenity::CreateAttachment::bytes(
+                jsonl.into_bytes(),
+                "feedback.jsonl",
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
 /// Clears the set of active channel IDs
-#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+#[poise::command(slash_command, check = "can_manage_channels")]
 pub async fn clear_active_channels(ctx: PoiseContext<'_>) -> Result<(), CommandError> {
     let Some(guild_id) = ctx.guild_id() else {
         ctx.say("Couldn't get server id").await?;
         return Ok(());
     };
 
-    ctx.data().db.clear_active_channel_ids(guild_id.get())?;
+    ctx.data()
+        .db
+        .clear_active_channel_ids(guild_id.get(), ctx.author().id.get())?;
 
     ctx.say("Cleared the set of active channel ids").await?;
 
     Ok(())
 }
+
+/// Sets the role whose members can change the parts of the config you choose
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn set_bot_manager_role(
+    ctx: PoiseContext<'_>,
+    #[description = "Leave empty to remove the bot manager role"] role: Option<serenity::Role>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data().db.set_bot_manager_role_id(
+        guild_id.get(),
+        ctx.author().id.get(),
+        role.as_ref().map(|r| r.id.get()),
+    )?;
+
+    if let Some(role) = role {
+        ctx.say(format!("Bot manager role set to '{}'", role.name))
+            .await?;
+    } else {
+        ctx.say("Removed the bot manager role").await?;
+    }
+
+    Ok(())
+}
+
+/// Grants or revokes a permission for the bot manager role
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn set_bot_manager_permission(
+    ctx: PoiseContext<'_>,
+    #[description = "What bot managers may configure"] permission: ManagerPermission,
+    #[description = "Whether bot managers have the permission"] granted: bool,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.data().db.set_bot_manager_permission(
+        guild_id.get(),
+        ctx.author().id.get(),
+        permission,
+        granted,
+    )?;
+
+    if granted {
+        ctx.say(format!("Bot managers can now change '{permission}'"))
+            .await?;
+    } else {
+        ctx.say(format!("Bot managers can no longer change '{permission}'"))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets the channel config changes are posted in
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn set_mod_log_channel(
+    ctx: PoiseContext<'_>,
+    #[description = "Leave empty to stop posting config changes"]
+    #[channel_types("Text")]
+    channel: Option<serenity::Channel>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let channel_id = channel.map(|c| c.id());
+
+    ctx.data().db.set_mod_log_channel_id(
+        guild_id.get(),
+        ctx.author().id.get(),
+        channel_id.map(serenity::ChannelId::get),
+    )?;
+
+    if let Some(id) = channel_id {
+        ctx.say(format!("Config changes will be posted in {}", id.mention()))
+            .await?;
+    } else {
+        ctx.say("Config changes will no longer be posted").await?;
+    }
+
+    Ok(())
+}
+
+/// Shows who changed your server's config, and when
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn config_history(
+    ctx: PoiseContext<'_>,
+    #[description = "Page of changes, newest first (default: 1)"]
+    #[min = 1]
+    page: Option<usize>,
+) -> Result<(), CommandError> {
+    const PAGE_SIZE: usize = 10;
+
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let page = page.unwrap_or(1).max(1);
+    let (changes, total) =
+        ctx.data()
+            .db
+            .get_audit_log(guild_id.get(), (page - 1) * PAGE_SIZE, PAGE_SIZE)?;
+
+    let content = if changes.is_empty() {
+        String::from("No config changes on this page")
+    } else {
+        format!(
+            "{}\n-# Page {page} of {}",
+            changes.iter().map(format_change).join("\n"),
+            total.div_ceil(PAGE_SIZE)
+        )
+    };
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(content)
+            .allowed_mentions(serenity::CreateAllowedMentions::new())
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
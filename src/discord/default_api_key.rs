@@ -0,0 +1,169 @@
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashSet;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+
+use crate::claude;
+use crate::database::{self, DatabaseClientError, Record};
+
+/// The operator's API key, used by allowed servers that don't have their own,
+/// with its own daily token budget
+#[derive(Clone, Default)]
+pub struct DefaultApiKey {
+    api_key: Option<Arc<str>>,
+    guild_ids: Arc<HashSet<u64>>,
+    daily_token_budget: Option<NonZeroU64>,
+}
+
+/// Whose API key a request is made with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySource {
+    /// The server's own key
+    Server,
+    /// The operator's default key, which has a daily budget
+    Default,
+}
+
+/// An API key to make a request with, and whose it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApiKey<'a> {
+    pub key: &'a str,
+    pub source: KeySource,
+}
+
+impl ApiKey<'_> {
+    /// Counts `usage` against today's budget if it was from a request made
+    /// with the default key
+    pub fn record_usage(
+        self,
+        db: &database::Client,
+        usage: claude::Usage,
+    ) -> Result<(), DatabaseClientError> {
+        if self.source != KeySource::Default {
+            return Ok(());
+        }
+
+        db.add_default_key_usage(today(), usage.input_tokens + usage.output_tokens)
+    }
+}
+
+/// Days since the Unix epoch, in UTC
+fn today() -> i64 {
+    chrono::Utc::now().timestamp().div_euclid(86_400)
+}
+
+impl DefaultApiKey {
+    pub fn new(
+        api_key: &str,
+        guild_ids: HashSet<u64>,
+        daily_token_budget: Option<NonZeroU64>,
+    ) -> Self {
+        Self {
+            api_key: Some(api_key.into()),
+            guild_ids: Arc::new(guild_ids),
+            daily_token_budget,
+        }
+    }
+
+    /// Whether a server without its own key may use the default one, given
+    /// how many tokens it's been used for today
+    fn usable_by(&self, server_id: u64, used_today: u64) -> bool {
+        self.api_key.is_some()
+            && self.guild_ids.contains(&server_id)
+            && self
+                .daily_token_budget
+                .is_none_or(|budget| used_today < budget.get())
+    }
+
+    /// The API key to use for requests from a server: its own, or the default
+    /// one if it may use it and there's budget left today
+    pub fn api_key_for<'a>(
+        &'a self,
+        db: &database::Client,
+        server_id: u64,
+        server_config: &'a Record,
+    ) -> Result<Option<ApiKey<'a>>, DatabaseClientError> {
+        if let Some(key) = &server_config.claude_api_key {
+            return Ok(Some(ApiKey {
+                key,
+                source: KeySource::Server,
+            }));
+        }
+
+        if self.api_key.is_none() || !self.guild_ids.contains(&server_id) {
+            return Ok(None);
+        }
+
+        let used_today = match self.daily_token_budget {
+            Some(_) => db.get_default_key_usage(today())?,
+            None => 0,
+        };
+
+        if !self.usable_by(server_id, used_today) {
+            log::warn!("Default API key is out of budget for today");
+            return Ok(None);
+        }
+
+        Ok(self.api_key.as_deref().map(|key| ApiKey {
+            key,
+            source: KeySource::Default,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiKey, DefaultApiKey, KeySource};
+    use std::num::NonZeroU64;
+
+    #[test]
+    fn only_allowed_servers_within_budget() {
+        let key = DefaultApiKey::new("key", [1].into(), NonZeroU64::new(100));
+
+        assert!(key.usable_by(1, 99));
+        assert!(!key.usable_by(1, 100));
+        assert!(!key.usable_by(2, 0));
+        assert!(!DefaultApiKey::default().usable_by(1, 0));
+    }
+
+    #[test]
+    fn servers_keys_used_first() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let db = crate::database::Client::new(&file.path().to_path_buf()).unwrap();
+        let key = DefaultApiKey::new("default", [1].into(), NonZeroU64::new(10));
+
+        let own = crate::database::Record {
+            claude_api_key: Some("own".to_string()),
+            ..Default::default()
+        };
+        // A server that stores the default key as its own pays for it itself
+        let copied = crate::database::Record {
+            claude_api_key: Some("default".to_string()),
+            ..Default::default()
+        };
+        let none = crate::database::Record::default();
+
+        let own_key = key.api_key_for(&db, 1, &own).unwrap().unwrap();
+        let copied_key = key.api_key_for(&db, 1, &copied).unwrap().unwrap();
+        let default_key = ApiKey {
+            key: "default",
+            source: KeySource::Default,
+        };
+        assert_eq!(own_key.source, KeySource::Server);
+        assert_eq!(copied_key.source, KeySource::Server);
+        assert_eq!(key.api_key_for(&db, 1, &none).unwrap(), Some(default_key));
+        assert_eq!(key.api_key_for(&db, 2, &none).unwrap(), None);
+
+        let usage = crate::claude::Usage {
+            input_tokens: 6,
+            output_tokens: 4,
+        };
+        own_key.record_usage(&db, usage).unwrap();
+        copied_key.record_usage(&db, usage).unwrap();
+        assert_eq!(key.api_key_for(&db, 1, &none).unwrap(), Some(default_key));
+
+        default_key.record_usage(&db, usage).unwrap();
+        assert_eq!(key.api_key_for(&db, 1, &none).unwrap(), None);
+    }
+}
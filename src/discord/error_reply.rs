@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
 pub enum ErrorReply {
     CantSeeReplies,
     InactiveChannel,
@@ -5,11 +8,50 @@ pub enum ErrorReply {
     SomethingWentWrong,
     MaxTokens,
     TermsOfServiceViolation,
+    ResponseUnavailable,
+    NotYourResponse,
+    NoResponse,
+    NotAnImage,
+    NothingToSummarize,
+    TooMuchToSummarize,
+    /// Unix timestamp of when the user can try again
+    RateLimited(i64),
+    AccessDenied,
 }
 
 impl ErrorReply {
-    pub fn pretty_str(&self) -> &'static str {
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        let retry_after = i64::try_from(retry_after.as_secs()).unwrap_or(i64::MAX);
+
+        ErrorReply::RateLimited(
+            chrono::Utc::now()
+                .timestamp()
+                .saturating_add(retry_after + 1),
+        )
+    }
+
+    /// A stable name for the kind of error, for metrics
+    pub fn kind(&self) -> &'static str {
         match self {
+            ErrorReply::CantSeeReplies => "cant_see_replies",
+            ErrorReply::InactiveChannel => "inactive_channel",
+            ErrorReply::MissingAPIKey => "missing_api_key",
+            ErrorReply::SomethingWentWrong => "something_went_wrong",
+            ErrorReply::MaxTokens => "max_tokens",
+            ErrorReply::TermsOfServiceViolation => "terms_of_service_violation",
+            ErrorReply::ResponseUnavailable => "response_unavailable",
+            ErrorReply::NotYourResponse => "not_your_response",
+            ErrorReply::NoResponse => "no_response",
+            ErrorReply::NotAnImage => "not_an_image",
+            ErrorReply::NothingToSummarize => "nothing_to_summarize",
+            ErrorReply::TooMuchToSummarize => "too_much_to_summarize",
+            ErrorReply::RateLimited(_) => "rate_limited",
+            ErrorReply::AccessDenied => "access_denied",
+        }
+    }
+
+    pub fn pretty_str(&self) -> Cow<'static, str> {
+        let reply = match self {
             ErrorReply::CantSeeReplies => {
                 "*Claude can't see replies. View the tracking issue* [here](<https://github.com/wyatt-avilla/claude-discord-bot/issues/18>)."
             }
@@ -24,6 +66,24 @@ impl ErrorReply {
             ErrorReply::TermsOfServiceViolation => {
                 "*Content in this interaction violates Anthropic's terms of service*"
             }
-        }
+            ErrorReply::ResponseUnavailable => {
+                "*This response can no longer be changed, the message it responded to may have been deleted*"
+            }
+            ErrorReply::NotYourResponse => {
+                "*Only the person who triggered this response or a moderator can change it*"
+            }
+            ErrorReply::NoResponse => "*Claude chose not to respond*",
+            ErrorReply::NotAnImage => "*Claude can only look at image attachments*",
+            ErrorReply::NothingToSummarize => "*There are no messages to summarize*",
+            ErrorReply::TooMuchToSummarize => "*There's too much to summarize, try fewer messages*",
+            ErrorReply::AccessDenied => "*You aren't allowed to ask Claude for anything here*",
+            ErrorReply::RateLimited(retry_at) => {
+                return Cow::Owned(format!(
+                    "*Claude is getting too many requests, try again <t:{retry_at}:R>*"
+                ));
+            }
+        };
+
+        Cow::Borrowed(reply)
     }
 }
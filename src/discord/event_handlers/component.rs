@@ -0,0 +1,658 @@
+#![allow(clippy::result_large_err)]
+
+use std::time::Instant;
+
+use poise::serenity_prelude as serenity;
+
+use super::feedback::{FeedbackSource, track_feedback};
+use crate::claude;
+use crate::database::ResponseRecord;
+use crate::discord::access;
+use crate::discord::client::CustomData;
+use crate::discord::error_reply::ErrorReply;
+use crate::discord::message::{MESSAGE_LIMIT, split_message};
+use crate::discord::{
+    CommandError, MessageContext, MessageOrigin, OutgoingMentions, SerenityMessageContext,
+};
+use crate::metrics;
+
+const CONTINUE_PROMPT: &str =
+    "*Your last message was cut off. Continue it exactly where it left off.*";
+
+/// Buttons attached to Claude's responses
+#[derive(Debug, PartialEq, Eq)]
+enum ResponseButton {
+    Regenerate,
+    Delete,
+    Continue,
+}
+
+impl ResponseButton {
+    fn prefix(&self) -> &'static str {
+        match self {
+            ResponseButton::Regenerate => "claude_regenerate",
+            ResponseButton::Delete => "claude_delete",
+            ResponseButton::Continue => "claude_continue",
+        }
+    }
+
+    fn custom_id(&self, trigger_id: serenity::MessageId) -> String {
+        format!("{}:{trigger_id}", self.prefix())
+    }
+
+    fn parse(custom_id: &str) -> Option<(Self, serenity::MessageId)> {
+        let (prefix, id) = custom_id.split_once(':')?;
+        let id = id.parse().ok().filter(|&id| id != 0)?;
+
+        [
+            ResponseButton::Regenerate,
+            ResponseButton::Delete,
+            ResponseButton::Continue,
+        ]
+        .into_iter()
+        .find(|b| b.prefix() == prefix)
+        .map(|b| (b, serenity::MessageId::new(id)))
+    }
+
+    fn create(&self, trigger_id: serenity::MessageId) -> serenity::CreateButton {
+        let (label, emoji) = match self {
+            ResponseButton::Regenerate => ("Regenerate", '🔄'),
+            ResponseButton::Delete => ("Delete", '🗑'),
+            ResponseButton::Continue => ("Continue", '⏩'),
+        };
+
+        serenity::CreateButton::new(self.custom_id(trigger_id))
+            .label(label)
+            .emoji(emoji)
+            .style(serenity::ButtonStyle::Secondary)
+    }
+}
+
+/// The buttons for the last message of a response to `trigger_id`
+pub fn response_buttons(
+    trigger_id: serenity::MessageId,
+    truncated: bool,
+) -> serenity::CreateActionRow {
+    let mut buttons = vec![
+        ResponseButton::Regenerate.create(trigger_id),
+        ResponseButton::Delete.create(trigger_id),
+    ];
+
+    if truncated {
+        buttons.push(ResponseButton::Continue.create(trigger_id));
+    }
+
+    serenity::CreateActionRow::Buttons(buttons)
+}
+
+const SHARE_PREFIX: &str = "claude_share";
+
+/// A button on an ephemeral response that posts it to the channel, replying to
+/// `reply_to` if there's a message it's about
+pub fn share_button(reply_to: Option<serenity::MessageId>) -> serenity::CreateActionRow {
+    let custom_id = format!(
+        "{SHARE_PREFIX}:{}",
+        reply_to.map_or(0, serenity::MessageId::get)
+    );
+
+    serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(custom_id)
+            .label("Post to channel")
+            .emoji('📢')
+            .style(serenity::ButtonStyle::Secondary),
+    ])
+}
+
+/// A click on a share button
+#[derive(Debug, PartialEq, Eq)]
+struct Share {
+    reply_to: Option<serenity::MessageId>,
+}
+
+fn parse_share(custom_id: &str) -> Option<Share> {
+    let id: u64 = custom_id
+        .strip_prefix(SHARE_PREFIX)?
+        .strip_prefix(':')?
+        .parse()
+        .ok()?;
+
+    Some(Share {
+        reply_to: (id != 0).then(|| serenity::MessageId::new(id)),
+    })
+}
+
+pub async fn handle_component(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+) -> Result<(), CommandError> {
+    if let Some(Share { reply_to }) = parse_share(&interaction.data.custom_id) {
+        return share(ctx, interaction, custom_data, reply_to).await;
+    }
+
+    let Some((button, trigger_id)) = ResponseButton::parse(&interaction.data.custom_id) else {
+        return Ok(());
+    };
+
+    let Some(responses) = custom_data.db.get_responses(trigger_id.get())? else {
+        return ephemeral_reply(ctx, interaction, ErrorReply::ResponseUnavailable).await;
+    };
+
+    let is_moderator = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(serenity::Permissions::manage_messages);
+
+    if interaction.user.id.get() != responses.trigger_author_id && !is_moderator {
+        return ephemeral_reply(ctx, interaction, ErrorReply::NotYourResponse).await;
+    }
+
+    match button {
+        ResponseButton::Delete => {
+            delete(ctx, interaction, custom_data, trigger_id, responses).await
+        }
+        ResponseButton::Regenerate => {
+            regenerate(ctx, interaction, custom_data, trigger_id, responses).await
+        }
+        ResponseButton::Continue => {
+            continue_response(ctx, interaction, custom_data, trigger_id, responses).await
+        }
+    }
+}
+
+async fn ephemeral_reply(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    reply: ErrorReply,
+) -> Result<(), CommandError> {
+    metrics::record_error_reply(reply.kind());
+
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Message(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(reply.pretty_str())
+                    .ephemeral(true),
+            ),
+        )
+        .await
+        .inspect_err(|_| metrics::record_send_failure("interaction_response"))?;
+
+    Ok(())
+}
+
+async fn ephemeral_followup(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    reply: ErrorReply,
+) -> Result<(), CommandError> {
+    metrics::record_error_reply(reply.kind());
+
+    interaction
+        .create_followup(
+            ctx,
+            serenity::CreateInteractionResponseFollowup::new()
+                .content(reply.pretty_str())
+                .ephemeral(true),
+        )
+        .await
+        .inspect_err(|_| metrics::record_send_failure("interaction_response"))?;
+
+    Ok(())
+}
+
+async fn share(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+    reply_to: Option<serenity::MessageId>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+
+    let config = custom_data.db.get_config(guild_id.get())?;
+
+    let mut message = serenity::CreateMessage::new()
+        .content(format!(
+            "{}\n-# Shared by {}",
+            interaction.message.content,
+            interaction.user.display_name()
+        ))
+        .allowed_mentions(config.mention_policy.allowed_mentions());
+
+    if let Some(id) = reply_to {
+        message = message.reference_message((interaction.channel_id, id));
+    }
+
+    interaction
+        .channel_id
+        .send_message(ctx, message)
+        .await
+        .inspect_err(|_| metrics::record_send_failure("message"))?;
+
+    interaction
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new().components(vec![]),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn delete(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+    trigger_id: serenity::MessageId,
+    responses: ResponseRecord,
+) -> Result<(), CommandError> {
+    interaction.defer(ctx).await?;
+    custom_data.db.take_responses(trigger_id.get())?;
+
+    let channel_id = serenity::ChannelId::new(responses.channel_id);
+    for id in responses
+        .message_ids
+        .into_iter()
+        .map(serenity::MessageId::new)
+    {
+        if let Err(e) = channel_id.delete_message(ctx, id).await {
+            log::warn!("Couldn't delete response {id} in channel id {channel_id} ({e})");
+        }
+    }
+
+    Ok(())
+}
+
+/// A response from Claude, and what's needed to send it
+struct Requested {
+    response: claude::Response,
+    mentions: OutgoingMentions,
+    model: claude::Model,
+}
+
+/// A response from Claude to some message history, or the reason there isn't
+/// one. The request is checked against the access lists and counts against
+/// the rate limits of whoever clicked the button.
+async fn request_response(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+    guild_id: serenity::GuildId,
+    latest_message: serenity::Message,
+    extra_messages: Vec<claude::Message>,
+) -> Result<Result<Requested, ErrorReply>, CommandError> {
+    let server_config = custom_data.db.get_config(guild_id.get())?;
+
+    let role_ids = interaction
+        .member
+        .as_ref()
+        .map(|m| m.roles.as_slice())
+        .unwrap_or_default();
+
+    if !access::has_access(&server_config, interaction.user.id, role_ids) {
+        return Ok(Err(ErrorReply::AccessDenied));
+    }
+
+    let Some(api_key) =
+        custom_data
+            .default_api_key
+            .api_key_for(&custom_data.db, guild_id.get(), &server_config)?
+    else {
+        return Ok(Err(ErrorReply::MissingAPIKey));
+    };
+
+    if let Err(retry_after) = custom_data.rate_limiter.acquire(
+        &server_config,
+        guild_id,
+        interaction.channel_id,
+        interaction.user.id,
+        role_ids,
+        Instant::now(),
+    ) {
+        return Ok(Err(ErrorReply::rate_limited(retry_after)));
+    }
+
+    let message_context = SerenityMessageContext {
+        context: ctx.clone(),
+        message: latest_message,
+        origin: MessageOrigin::Regenerate,
+    };
+
+    let _typing = message_context.start_typing();
+
+    let history = message_context
+        .message_history(custom_data.claude.message_context_length())
+        .await?;
+    let local_time = server_config.local_time();
+    let mut msgs = message_context.claude_messages(&history, local_time);
+    msgs.extend(extra_messages);
+    let mentions = OutgoingMentions::new(&history, server_config.mention_policy.clone());
+
+    let response = match custom_data
+        .claude
+        .get_response(&msgs, api_key.key, &server_config.model, &local_time.now())
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Error requesting response from Claude ({e})");
+            return Ok(Err(ErrorReply::SomethingWentWrong));
+        }
+    };
+
+    if let Err(e) = api_key.record_usage(&custom_data.db, response.usage) {
+        log::error!("Couldn't record default API key usage ({e})");
+    }
+
+    match response.stop_reason {
+        claude::StopReason::Refusal => Ok(Err(ErrorReply::TermsOfServiceViolation)),
+        claude::StopReason::MaxTokens if response.content.is_empty() => {
+            Ok(Err(ErrorReply::MaxTokens))
+        }
+        _ => Ok(Ok(Requested {
+            response,
+            mentions,
+            model: server_config.model,
+        })),
+    }
+}
+
+/// Reacts to `message` with Claude's reactions, returning the text of
+/// Claude's messages
+async fn apply_reactions(
+    ctx: &serenity::Context,
+    message: &serenity::Message,
+    actions: Vec<claude::Action>,
+) -> Result<Vec<String>, CommandError> {
+    let mut texts = vec![];
+
+    for action in actions {
+        match action {
+            claude::Action::SendMessage(txt) => texts.push(txt),
+            claude::Action::ReactToMessage(emoji) => {
+                message
+                    .react(ctx, emoji)
+                    .await
+                    .inspect_err(|_| metrics::record_send_failure("reaction"))?;
+            }
+            claude::Action::Pass => (),
+        }
+    }
+
+    Ok(texts)
+}
+
+/// Claude's messages with their mentions, split to fit in Discord messages
+fn message_pieces(texts: &[String], mentions: &OutgoingMentions) -> Vec<String> {
+    texts
+        .iter()
+        .flat_map(|txt| split_message(&mentions.mentionify(txt), MESSAGE_LIMIT))
+        .collect()
+}
+
+/// Sends `pieces` to `channel_id`, with the response buttons on the last one
+async fn send_pieces(
+    ctx: &serenity::Context,
+    channel_id: serenity::ChannelId,
+    pieces: &[String],
+    mentions: &OutgoingMentions,
+    trigger_id: serenity::MessageId,
+    truncated: bool,
+) -> Result<Vec<serenity::MessageId>, CommandError> {
+    let mut sent = vec![];
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let mut message = serenity::CreateMessage::new()
+            .content(piece)
+            .allowed_mentions(mentions.allowed_mentions());
+        if i == pieces.len() - 1 {
+            message = message.components(vec![response_buttons(trigger_id, truncated)]);
+        }
+
+        let response = channel_id
+            .send_message(ctx, message)
+            .await
+            .inspect_err(|_| metrics::record_send_failure("message"))?;
+        sent.push(response.id);
+    }
+
+    Ok(sent)
+}
+
+async fn regenerate(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+    trigger_id: serenity::MessageId,
+    responses: ResponseRecord,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+
+    interaction.defer(ctx).await?;
+
+    let channel_id = serenity::ChannelId::new(responses.channel_id);
+    let Ok(trigger) = channel_id.message(ctx, trigger_id).await else {
+        return ephemeral_followup(ctx, interaction, ErrorReply::ResponseUnavailable).await;
+    };
+
+    let Requested {
+        response,
+        mentions,
+        model,
+    } = match request_response(
+        ctx,
+        interaction,
+        custom_data,
+        guild_id,
+        trigger.clone(),
+        vec![],
+    )
+    .await?
+    {
+        Ok(r) => r,
+        Err(reply) => return ephemeral_followup(ctx, interaction, reply).await,
+    };
+
+    let truncated = response.stop_reason == claude::StopReason::MaxTokens;
+    let usage = response.usage;
+    let texts = apply_reactions(ctx, &trigger, response.content).await?;
+    let pieces = message_pieces(&texts, &mentions);
+
+    let Some((first, rest)) = pieces.split_first() else {
+        return ephemeral_followup(ctx, interaction, ErrorReply::NoResponse).await;
+    };
+
+    // The first piece replaces the message the button was on, and the rest
+    // follow it
+    let buttons = if rest.is_empty() {
+        vec![response_buttons(trigger_id, truncated)]
+    } else {
+        vec![]
+    };
+    interaction
+        .edit_response(
+            ctx,
+            serenity::EditInteractionResponse::new()
+                .content(first)
+                .allowed_mentions(mentions.allowed_mentions())
+                .components(buttons),
+        )
+        .await?;
+
+    let mut message_ids = vec![interaction.message.id];
+    message_ids.extend(send_pieces(ctx, channel_id, rest, &mentions, trigger_id, truncated).await?);
+
+    for id in responses
+        .message_ids
+        .iter()
+        .map(|&id| serenity::MessageId::new(id))
+        .filter(|&id| id != interaction.message.id)
+    {
+        if let Err(e) = channel_id.delete_message(ctx, id).await {
+            log::warn!("Couldn't delete response {id} in channel id {channel_id} ({e})");
+        }
+    }
+
+    custom_data.db.set_responses(
+        trigger_id.get(),
+        ResponseRecord {
+            message_ids: message_ids.iter().map(|id| id.get()).collect(),
+            truncated,
+            ..responses
+        },
+    )?;
+
+    track_feedback(
+        &custom_data.db,
+        &custom_data.claude,
+        &FeedbackSource {
+            guild_id,
+            channel_id,
+            trigger_message_id: trigger_id,
+            trigger: "regenerate_button",
+            model: &model,
+            usage,
+        },
+        &message_ids,
+    )?;
+
+    Ok(())
+}
+
+async fn continue_response(
+    ctx: &serenity::Context,
+    interaction: &serenity::ComponentInteraction,
+    custom_data: &CustomData<SerenityMessageContext>,
+    trigger_id: serenity::MessageId,
+    responses: ResponseRecord,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+
+    interaction.defer(ctx).await?;
+
+    let Requested {
+        response,
+        mentions,
+        model,
+    } = match request_response(
+        ctx,
+        interaction,
+        custom_data,
+        guild_id,
+        *interaction.message.clone(),
+        vec![claude::Message::user_text(CONTINUE_PROMPT)],
+    )
+    .await?
+    {
+        Ok(r) => r,
+        Err(reply) => return ephemeral_followup(ctx, interaction, reply).await,
+    };
+
+    let truncated = response.stop_reason == claude::StopReason::MaxTokens;
+    let usage = response.usage;
+    let texts = apply_reactions(ctx, &interaction.message, response.content).await?;
+    let pieces = message_pieces(&texts, &mentions);
+
+    if pieces.is_empty() {
+        return ephemeral_followup(ctx, interaction, ErrorReply::NoResponse).await;
+    }
+
+    interaction
+        .edit_response(
+            ctx,
+            serenity::EditInteractionResponse::new()
+                .components(vec![response_buttons(trigger_id, false)]),
+        )
+        .await?;
+
+    let sent = send_pieces(
+        ctx,
+        interaction.channel_id,
+        &pieces,
+        &mentions,
+        trigger_id,
+        truncated,
+    )
+    .await?;
+
+    custom_data.db.add_responses(
+        trigger_id.get(),
+        ResponseRecord {
+            message_ids: sent.iter().map(|id| id.get()).collect(),
+            truncated,
+            ..responses
+        },
+    )?;
+
+    track_feedback(
+        &custom_data.db,
+        &custom_data.claude,
+        &FeedbackSource {
+            guild_id,
+            channel_id: interaction.channel_id,
+            trigger_message_id: trigger_id,
+            trigger: "continue_button",
+            model: &model,
+            usage,
+        },
+        &sent,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResponseButton, Share, parse_share, share_button};
+    use poise::serenity_prelude as serenity;
+
+    #[test]
+    fn custom_id_round_trip() {
+        let id = serenity::MessageId::new(1234);
+
+        for button in [
+            ResponseButton::Regenerate,
+            ResponseButton::Delete,
+            ResponseButton::Continue,
+        ] {
+            let custom_id = button.custom_id(id);
+            assert_eq!(ResponseButton::parse(&custom_id), Some((button, id)));
+        }
+    }
+
+    #[test]
+    fn share_custom_id_round_trip() {
+        let id = serenity::MessageId::new(1234);
+
+        for reply_to in [Some(id), None] {
+            let serenity::CreateActionRow::Buttons(buttons) = share_button(reply_to) else {
+                panic!("share button isn't a button row");
+            };
+            let custom_id = serde_json::to_value(&buttons[0]).unwrap()["custom_id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            assert_eq!(parse_share(&custom_id), Some(Share { reply_to }));
+        }
+
+        assert_eq!(parse_share("claude_delete:1234"), None);
+    }
+
+    #[test]
+    fn unrelated_custom_id_ignored() {
+        assert_eq!(ResponseButton::parse("something_else:1234"), None);
+        assert_eq!(ResponseButton::parse("claude_delete"), None);
+        assert_eq!(ResponseButton::parse("claude_delete:abc"), None);
+    }
+}
@@ -0,0 +1,111 @@
+#![allow(clippy::result_large_err)]
+
+use poise::serenity_prelude as serenity;
+
+use crate::claude;
+use crate::database::{self, DatabaseClientError, FeedbackRecord, Vote};
+use crate::discord::CommandError;
+use crate::discord::SerenityMessageContext;
+use crate::discord::client::CustomData;
+
+/// What produced a set of Claude's messages
+pub struct FeedbackSource<'a> {
+    pub guild_id: serenity::GuildId,
+    pub channel_id: serenity::ChannelId,
+    pub trigger_message_id: serenity::MessageId,
+    pub trigger: &'a str,
+    pub model: &'a claude::Model,
+    pub usage: claude::Usage,
+}
+
+/// Starts tracking feedback on each of `message_ids`
+pub fn track_feedback(
+    db: &database::Client,
+    claude: &claude::Client,
+    source: &FeedbackSource,
+    message_ids: &[serenity::MessageId],
+) -> Result<(), DatabaseClientError> {
+    for id in message_ids {
+        db.add_feedback_target(FeedbackRecord {
+            message_id: id.get(),
+            guild_id: source.guild_id.get(),
+            channel_id: source.channel_id.get(),
+            trigger_message_id: source.trigger_message_id.get(),
+            trigger: source.trigger.to_string(),
+            model: source.model.id().to_string(),
+            system_prompt_version: claude.system_prompt_version(),
+            input_tokens: source.usage.input_tokens,
+            output_tokens: source.usage.output_tokens,
+            created_at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        })?;
+    }
+
+    Ok(())
+}
+
+fn vote(reaction: &serenity::ReactionType) -> Option<Vote> {
+    let serenity::ReactionType::Unicode(emoji) = reaction else {
+        return None;
+    };
+
+    // Skin tone modifiers follow the base emoji
+    if emoji.starts_with('👍') {
+        Some(Vote::Up)
+    } else if emoji.starts_with('👎') {
+        Some(Vote::Down)
+    } else {
+        None
+    }
+}
+
+pub fn handle_reaction(
+    ctx: &serenity::Context,
+    reaction: &serenity::Reaction,
+    added: bool,
+    custom_data: &CustomData<SerenityMessageContext>,
+) -> Result<(), CommandError> {
+    let Some(vote) = vote(&reaction.emoji) else {
+        return Ok(());
+    };
+
+    let Some(user_id) = reaction.user_id else {
+        return Ok(());
+    };
+
+    if user_id == ctx.cache.current_user().id {
+        return Ok(());
+    }
+
+    if custom_data
+        .db
+        .set_feedback_vote(reaction.message_id.get(), user_id.get(), vote, added)?
+    {
+        log::debug!(
+            "Recorded {vote:?} vote (added: {added}) on message id {}",
+            reaction.message_id
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vote;
+    use crate::database::Vote;
+    use poise::serenity_prelude::ReactionType;
+
+    #[test]
+    fn thumbs_are_votes() {
+        assert_eq!(
+            vote(&ReactionType::Unicode("👍".to_string())),
+            Some(Vote::Up)
+        );
+        assert_eq!(
+            vote(&ReactionType::Unicode("👎🏽".to_string())),
+            Some(Vote::Down)
+        );
+        assert_eq!(vote(&ReactionType::Unicode("❤️".to_string())), None);
+    }
+}
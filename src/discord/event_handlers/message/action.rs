@@ -1,12 +1,28 @@
 use crate::claude;
+use crate::metrics;
+use poise::serenity_prelude as serenity;
 
 use crate::discord::CommandError;
 use crate::discord::MessageContext;
+use crate::discord::OutgoingMentions;
 use crate::discord::error_reply::ErrorReply;
+use crate::discord::event_handlers::component::response_buttons;
 
 enum ChannelAction {
     ErrorReply(ErrorReply),
-    ClaudeActions(Vec<claude::Action>),
+    ClaudeActions {
+        actions: Vec<claude::Action>,
+        truncated: bool,
+        usage: claude::Usage,
+    },
+}
+
+/// The messages sent in response to a triggering message
+#[derive(Default)]
+pub struct SentResponse {
+    pub message_ids: Vec<serenity::MessageId>,
+    pub truncated: bool,
+    pub usage: Option<claude::Usage>,
 }
 
 fn channel_action_from_claude_response(
@@ -28,6 +44,17 @@ fn channel_action_from_claude_response(
     };
 
     match resp.stop_reason {
+        claude::StopReason::MaxTokens if !resp.content.is_empty() => {
+            log::warn!(
+                "Claude hit the max amount of tokens while trying to respond to '{}', sending partial response",
+                message.content()
+            );
+            Some(ChannelAction::ClaudeActions {
+                actions: resp.content,
+                truncated: true,
+                usage: resp.usage,
+            })
+        }
         claude::StopReason::MaxTokens => {
             log::error!(
                 "Claude hit the max amount of tokens while trying to respond to '{}'",
@@ -54,7 +81,11 @@ fn channel_action_from_claude_response(
                 log::error!("Empty response provided for '{}'", message.content());
                 None
             } else {
-                Some(ChannelAction::ClaudeActions(resp.content))
+                Some(ChannelAction::ClaudeActions {
+                    actions: resp.content,
+                    truncated: false,
+                    usage: resp.usage,
+                })
             }
         }
     }
@@ -66,7 +97,9 @@ pub async fn respond_with_claude_action(
     api_key: &str,
     model: claude::Model,
     messages: Vec<claude::Message>,
-) -> Result<(), CommandError> {
+    mentions: &OutgoingMentions,
+    now: &str,
+) -> Result<SentResponse, CommandError> {
     let mentioned = message_context.mentioned();
 
     let _typing = if mentioned {
@@ -77,29 +110,54 @@ pub async fn respond_with_claude_action(
 
     match channel_action_from_claude_response(
         &message_context,
-        claude.get_response(&messages, api_key, &model).await,
+        claude.get_response(&messages, api_key, &model, now).await,
     ) {
-        None => Ok(()),
+        None => Ok(SentResponse::default()),
         Some(ChannelAction::ErrorReply(reply)) => {
             message_context.error_reply(reply).await?;
-            Ok(())
+            Ok(SentResponse::default())
         }
-        Some(ChannelAction::ClaudeActions(actions)) => {
+        Some(ChannelAction::ClaudeActions {
+            actions,
+            truncated,
+            usage,
+        }) => {
             let (ctx, msg) = message_context.into_inner();
-            for action in actions {
+            let last_message = actions
+                .iter()
+                .rposition(|a| matches!(a, claude::Action::SendMessage(_)));
+
+            let mut sent = vec![];
+            for (i, action) in actions.into_iter().enumerate() {
                 match action {
                     claude::Action::SendMessage(txt) => {
-                        msg.channel_id.say(&ctx, txt).await?;
+                        let mut message = mentions.create_message(&txt);
+                        if Some(i) == last_message {
+                            message = message.components(vec![response_buttons(msg.id, truncated)]);
+                        }
+
+                        let response = msg
+                            .channel_id
+                            .send_message(&ctx, message)
+                            .await
+                            .inspect_err(|_| metrics::record_send_failure("message"))?;
+                        sent.push(response.id);
                     }
                     claude::Action::ReactToMessage(emoji) => {
-                        msg.react(&ctx, emoji.clone()).await?;
+                        msg.react(&ctx, emoji.clone())
+                            .await
+                            .inspect_err(|_| metrics::record_send_failure("reaction"))?;
                     }
                     claude::Action::Pass => {
                         log::warn!("Claude chose not to respond to '{}'", msg.content);
                     }
                 }
             }
-            Ok(())
+            Ok(SentResponse {
+                message_ids: sent,
+                truncated,
+                usage: Some(usage),
+            })
         }
     }
 }
@@ -110,7 +168,7 @@ mod tests {
     use crate::discord::MockMessageContext;
     use crate::discord::error_reply::ErrorReply;
     use crate::{
-        claude::{ClaudeError, Response, StopReason, Usage},
+        claude::{Action, ClaudeError, Response, StopReason, Usage},
         discord::event_handlers::message::action::channel_action_from_claude_response,
     };
 
@@ -183,6 +241,25 @@ mod tests {
         assert!(res.is_none());
     }
 
+    #[test]
+    fn max_tokens_with_content_partial_response() {
+        let mut ctx = MockMessageContext::new();
+        ctx.expect_mentioned().once().return_const(true);
+
+        let mut resp = response(StopReason::MaxTokens);
+        resp.content = vec![Action::SendMessage("partial".to_string())];
+
+        let res = channel_action_from_claude_response(&ctx, Ok(resp));
+
+        assert!(matches!(
+            res,
+            Some(ChannelAction::ClaudeActions {
+                truncated: true,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn refusal_mentioned_error_reply() {
         let mut ctx = MockMessageContext::new();
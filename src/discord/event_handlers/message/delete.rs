@@ -0,0 +1,89 @@
+use std::fmt::Display;
+
+use bincode::{Decode, Encode};
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+use crate::discord::CommandError;
+use crate::discord::SerenityMessageContext;
+use crate::discord::client::CustomData;
+
+const DELETED_TRIGGER_NOTE: &str = "-# *The message Claude responded to was deleted*";
+
+/// What to do with Claude's responses when the message that triggered them
+/// is deleted
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode, ChoiceParameter,
+)]
+pub enum DeletedTriggerAction {
+    #[name = "Keep the response"]
+    Keep,
+    #[name = "Mark the response"]
+    #[default]
+    Mark,
+    #[name = "Delete the response"]
+    Delete,
+}
+
+impl Display for DeletedTriggerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub async fn handle_message_delete(
+    ctx: &serenity::Context,
+    channel_id: serenity::ChannelId,
+    deleted_message_id: serenity::MessageId,
+    guild_id: Option<serenity::GuildId>,
+    custom_data: &CustomData<SerenityMessageContext>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = guild_id else {
+        return Ok(());
+    };
+
+    let Some(responses) = custom_data.db.take_responses(deleted_message_id.get())? else {
+        return Ok(());
+    };
+
+    let server_config = custom_data.db.get_config(guild_id.get())?;
+
+    for id in responses
+        .message_ids
+        .into_iter()
+        .map(serenity::MessageId::new)
+    {
+        let result = match server_config.deleted_trigger_action {
+            DeletedTriggerAction::Keep => Ok(()),
+            DeletedTriggerAction::Delete => channel_id.delete_message(ctx, id).await,
+            DeletedTriggerAction::Mark => mark_response(ctx, channel_id, id).await,
+        };
+
+        if let Err(e) = result {
+            log::warn!(
+                "Couldn't handle response {id} to deleted message {deleted_message_id} in channel id {channel_id} ({e})"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn mark_response(
+    ctx: &serenity::Context,
+    channel_id: serenity::ChannelId,
+    message_id: serenity::MessageId,
+) -> Result<(), serenity::Error> {
+    let mut message = channel_id.message(ctx, message_id).await?;
+
+    if message.content.ends_with(DELETED_TRIGGER_NOTE) {
+        return Ok(());
+    }
+
+    let content = format!("{}\n{DELETED_TRIGGER_NOTE}", message.content);
+
+    message
+        .edit(ctx, serenity::EditMessage::new().content(content))
+        .await
+}
@@ -0,0 +1,118 @@
+use poise::serenity_prelude as serenity;
+
+use crate::discord::CommandError;
+use crate::discord::client::CustomData;
+use crate::discord::{MessageOrigin, SerenityMessageContext};
+
+/// Decides whether an edited message should be handled again
+fn edit_origin(
+    already_responded: bool,
+    regenerate_on_edit: bool,
+    newly_mentioned: bool,
+) -> Option<MessageOrigin> {
+    if already_responded {
+        return regenerate_on_edit.then_some(MessageOrigin::Regenerate);
+    }
+
+    newly_mentioned.then_some(MessageOrigin::Edited)
+}
+
+pub async fn handle_message_update(
+    ctx: &serenity::Context,
+    old: Option<&serenity::Message>,
+    new: Option<&serenity::Message>,
+    event: &serenity::MessageUpdateEvent,
+    custom_data: &CustomData<SerenityMessageContext>,
+) -> Result<(), CommandError> {
+    // Embeds being resolved also trigger updates, only content edits matter
+    if event.content.is_none() {
+        return Ok(());
+    }
+
+    let Some(guild_id) = event.guild_id else {
+        return Ok(());
+    };
+
+    let mut message = match new {
+        Some(msg) => msg.clone(),
+        None => event.channel_id.message(ctx, event.id).await?,
+    };
+
+    let bot_id = ctx.cache.current_user().id;
+    if message.author.id == bot_id {
+        return Ok(());
+    }
+
+    // Messages fetched over HTTP don't have the author's roles, which access
+    // and rate limit exemptions go by
+    if message.member.is_none() {
+        message.member = match event.member.clone().flatten() {
+            Some(member) => Some(member),
+            None => match guild_id.member(ctx, message.author.id).await {
+                Ok(member) => Some(Box::new(member.into())),
+                Err(e) => {
+                    log::warn!(
+                        "Couldn't get member {} in server id {guild_id} ({e})",
+                        message.author.id
+                    );
+                    None
+                }
+            },
+        };
+    }
+
+    let server_config = custom_data.db.get_config(guild_id.get())?;
+    let responses = custom_data.db.get_responses(message.id.get())?;
+
+    let newly_mentioned =
+        message.mentions_user_id(bot_id) && !old.is_some_and(|m| m.mentions_user_id(bot_id));
+
+    let Some(origin) = edit_origin(
+        responses.is_some(),
+        server_config.regenerate_on_edit,
+        newly_mentioned,
+    ) else {
+        return Ok(());
+    };
+
+    // The old responses are replaced once new ones are sent, see
+    // `handle_queued_message`
+    super::handle_message(
+        SerenityMessageContext {
+            context: ctx.clone(),
+            message,
+            origin,
+        },
+        custom_data,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edit_origin;
+    use crate::discord::MessageOrigin;
+
+    #[test]
+    fn new_mention_handled_as_edit() {
+        assert_eq!(edit_origin(false, false, true), Some(MessageOrigin::Edited));
+    }
+
+    #[test]
+    fn no_mention_ignored() {
+        assert_eq!(edit_origin(false, true, false), None);
+    }
+
+    #[test]
+    fn responded_regenerates_when_enabled() {
+        assert_eq!(
+            edit_origin(true, true, false),
+            Some(MessageOrigin::Regenerate)
+        );
+    }
+
+    #[test]
+    fn responded_ignored_when_disabled() {
+        assert_eq!(edit_origin(true, false, true), None);
+    }
+}
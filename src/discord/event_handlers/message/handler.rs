@@ -1,19 +1,36 @@
-use crate::database::Record;
+#![allow(clippy::result_large_err)]
 
+use crate::database::{Record, ResponseRecord};
+
+use super::super::feedback::{FeedbackSource, track_feedback};
 use super::response_intent::{ResponseIntent, classify_response};
 use crate::claude;
 use crate::database;
 use crate::discord::CommandError;
-use crate::discord::MessageContext;
 use crate::discord::client::CustomData;
 use crate::discord::error_reply::ErrorReply;
+use crate::discord::{ApiKey, DefaultApiKey, OutgoingMentions, RateLimiter};
+use crate::discord::{MessageContext, MessageOrigin};
+use dashmap::Entry;
 use poise::serenity_prelude::{self as serenity};
 use rand::Rng;
+use std::ops::ControlFlow;
 use tokio::sync::mpsc;
 
 pub enum ResponseTrigger {
     Mention,
     RandomChance,
+    Regenerate,
+}
+
+impl ResponseTrigger {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResponseTrigger::Mention => "mention",
+            ResponseTrigger::RandomChance => "random_chance",
+            ResponseTrigger::Regenerate => "regenerate",
+        }
+    }
 }
 
 fn random_interaction_triggered(server_config: &Record) -> bool {
@@ -31,78 +48,256 @@ fn response_trigger(
         return Some(ResponseTrigger::Mention);
     }
 
-    if random_interaction_triggered {
-        return Some(ResponseTrigger::RandomChance);
+    match message.origin() {
+        MessageOrigin::Regenerate => Some(ResponseTrigger::Regenerate),
+        MessageOrigin::Created if random_interaction_triggered => {
+            Some(ResponseTrigger::RandomChance)
+        }
+        MessageOrigin::Created | MessageOrigin::Edited => None,
     }
-
-    None
 }
 
 async fn handler_task(
     id: serenity::ChannelId,
     db: database::Client,
     claude: claude::Client,
+    rate_limiter: RateLimiter,
+    default_api_key: DefaultApiKey,
     mut rx: mpsc::Receiver<impl MessageContext>,
 ) {
     while let Some(message_context) = rx.recv().await {
-        let Some(Ok(server_config)) = message_context
-            .server_id()
-            .map(|id| db.get_config(id.into()))
-        else {
-            log::error!(
-                "Couldn't get server config when trying to process message '{}'",
-                message_context.content()
-            );
+        if handle_queued_message(
+            id,
+            message_context,
+            &db,
+            &claude,
+            &rate_limiter,
+            &default_api_key,
+        )
+        .await
+        .is_break()
+        {
             break;
+        }
+    }
+
+    log::info!("Task for channel id {id} exiting...");
+}
+
+/// Responds to a message from channel `id`'s queue if it should be, returning
+/// `Break` if the channel's task can't go on. Everything logged while handling
+/// it, including the Claude request, is in a span identifying the message.
+#[tracing::instrument(
+    name = "message",
+    skip_all,
+    fields(
+        guild_id = message_context.server_id().map(serenity::GuildId::get),
+        channel_id = id.get(),
+        message_id = message_context.message_id().get(),
+        trigger = tracing::field::Empty,
+        model = tracing::field::Empty,
+        request_id = tracing::field::Empty,
+    )
+)]
+async fn handle_queued_message(
+    id: serenity::ChannelId,
+    message_context: impl MessageContext,
+    db: &database::Client,
+    claude: &claude::Client,
+    rate_limiter: &RateLimiter,
+    default_api_key: &DefaultApiKey,
+) -> ControlFlow<()> {
+    let Some(Ok(server_config)) = message_context
+        .server_id()
+        .map(|id| db.get_config(id.into()))
+    else {
+        log::error!(
+            "Couldn't get server config when trying to process message '{}'",
+            message_context.content()
+        );
+        return ControlFlow::Break(());
+    };
+
+    let Some(response_trigger) = response_trigger(
+        &message_context,
+        random_interaction_triggered(&server_config),
+    ) else {
+        return ControlFlow::Continue(());
+    };
+    tracing::Span::current().record("trigger", response_trigger.name());
+
+    // Only looked up for messages that might be responded to, since checking
+    // the default key's budget reads the database
+    let api_key = match message_context
+        .server_id()
+        .map(|id| default_api_key.api_key_for(db, id.get(), &server_config))
+        .transpose()
+    {
+        Ok(api_key) => api_key.flatten(),
+        Err(e) => {
+            log::error!("Couldn't get the API key for channel id {id} ({e})");
+            return ControlFlow::Continue(());
+        }
+    };
+
+    match classify_response(
+        &response_trigger,
+        &message_context,
+        &server_config,
+        api_key,
+        rate_limiter,
+    ) {
+        ResponseIntent::ShouldNotRespond => ControlFlow::Continue(()),
+        ResponseIntent::ErrorReplyWith(reply) => {
+            if message_context.error_reply(reply).await.is_err() {
+                log::error!("Unable to reply in channel id {id}");
+                return ControlFlow::Break(());
+            }
+
+            ControlFlow::Continue(())
+        }
+        ResponseIntent::ShouldRespondWith { api_key, model } => {
+            tracing::Span::current().record("model", model.id());
+
+            respond(
+                message_context,
+                db,
+                claude,
+                &server_config,
+                &response_trigger,
+                api_key,
+            )
+            .await
+        }
+    }
+}
+
+/// Responds to a message with Claude, recording the responses and what they
+/// cost, and replacing any earlier responses if it's being regenerated
+async fn respond(
+    message_context: impl MessageContext,
+    db: &database::Client,
+    claude: &claude::Client,
+    server_config: &Record,
+    response_trigger: &ResponseTrigger,
+    api_key: ApiKey<'_>,
+) -> ControlFlow<()> {
+    let id = message_context.channel_id();
+    let model = &server_config.model;
+
+    let history = match message_context
+        .message_history(claude.message_context_length())
+        .await
+    {
+        Ok(history) => history,
+        Err(e) => {
+            log::error!("Unable to retrieve message history in channel id {id} ({e})");
+            return ControlFlow::Break(());
+        }
+    };
+
+    let local_time = server_config.local_time();
+    let msgs = message_context.claude_messages(&history, local_time);
+    let trigger_id = message_context.message_id();
+    let trigger_author_id = message_context.author_id();
+    let guild_id = message_context.server_id();
+    let mentions = OutgoingMentions::new(&history, server_config.mention_policy.clone());
+
+    // Kept until the regenerated responses are sent, so a failed request
+    // doesn't leave the message without any
+    let replaced = if message_context.origin() == MessageOrigin::Regenerate {
+        db.get_responses(trigger_id.get())
+            .inspect_err(|e| {
+                log::error!("Couldn't get responses to message id {trigger_id} ({e})");
+            })
+            .ok()
+            .flatten()
+            .map(|old| (message_context.clone(), old))
+    } else {
+        None
+    };
+
+    let sent = match super::action::respond_with_claude_action(
+        message_context,
+        claude,
+        api_key.key,
+        model.clone(),
+        msgs,
+        &mentions,
+        &local_time.now(),
+    )
+    .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            log::error!("Unable respond with action in channel id {id} ({e})");
+            return ControlFlow::Break(());
+        }
+    };
+
+    if !sent.message_ids.is_empty() {
+        let responses = ResponseRecord {
+            channel_id: id.get(),
+            message_ids: sent.message_ids.iter().map(|id| id.get()).collect(),
+            trigger_author_id: trigger_author_id.get(),
+            truncated: sent.truncated,
         };
 
-        let Some(response_trigger) = response_trigger(
-            &message_context,
-            random_interaction_triggered(&server_config),
-        ) else {
-            continue;
+        let recorded = match &replaced {
+            Some(_) => db.set_responses(trigger_id.get(), responses),
+            None => db.add_responses(trigger_id.get(), responses),
         };
+        if let Err(e) = recorded {
+            log::error!("Couldn't record responses to message id {trigger_id} ({e})");
+        }
 
-        match classify_response(&response_trigger, &message_context, &server_config) {
-            ResponseIntent::ShouldNotRespond => (),
-            ResponseIntent::ErrorReplyWith(reply) => {
-                if message_context.error_reply(reply).await.is_err() {
-                    log::error!("Unable to reply in channel id {id}");
-                    break;
-                }
-            }
-            ResponseIntent::ShouldRespondWith { api_key, model } => {
-                let msgs = match message_context.get_claude_messages().await {
-                    Ok(msgs) => msgs,
-                    Err(e) => {
-                        log::error!("Unable to retrieve message history in channel id {id} ({e})");
-                        break;
-                    }
-                };
-
-                if let Err(e) = super::action::respond_with_claude_action(
-                    message_context,
-                    &claude,
-                    api_key,
-                    model.clone(),
-                    msgs,
+        if let Some((message_context, old)) = replaced {
+            message_context
+                .delete_messages(
+                    old.message_ids
+                        .into_iter()
+                        .map(serenity::MessageId::new)
+                        .collect(),
                 )
-                .await
-                {
-                    log::error!("Unable respond with action in channel id {id} ({e})");
-                    break;
-                }
-            }
+                .await;
         }
     }
 
-    log::error!("Task for channel id {id} exiting...");
+    if let Some(usage) = sent.usage
+        && let Err(e) = api_key.record_usage(db, usage)
+    {
+        log::error!("Couldn't record default API key usage ({e})");
+    }
+
+    if let (Some(usage), Some(guild_id)) = (sent.usage, guild_id)
+        && let Err(e) = track_feedback(
+            db,
+            claude,
+            &FeedbackSource {
+                guild_id,
+                channel_id: id,
+                trigger_message_id: trigger_id,
+                trigger: response_trigger.name(),
+                model,
+                usage,
+            },
+            &sent.message_ids,
+        )
+    {
+        log::error!("Couldn't track feedback on responses to message id {trigger_id} ({e})");
+    }
+
+    ControlFlow::Continue(())
 }
 
 pub async fn handle_message<CTX: MessageContext + 'static>(
     msg_ctx: CTX,
     custom_data: &CustomData<CTX>,
 ) -> Result<(), CommandError> {
+    if custom_data.shutdown.is_started() {
+        return Ok(());
+    }
+
     let server_config = match msg_ctx
         .server_id()
         .map(|id| custom_data.db.get_config(id.into()))
@@ -131,28 +326,46 @@ pub async fn handle_message<CTX: MessageContext + 'static>(
 
     let sender_entry = || custom_data.channel_senders.entry(channel_id);
 
-    let tx_from_new_task = || {
-        let (tx, rx) = mpsc::channel::<_>(128);
+    // Spawned while `entry` is held, so a shutdown can't clear the senders
+    // between the task being spawned and its sender being stored. Returns
+    // `None` if the bot is shutting down.
+    let tx_from_new_task = |entry: Entry<'_, serenity::ChannelId, mpsc::Sender<CTX>>| {
+        let (tx, rx) = mpsc::channel::<_>(custom_data.channel_queue_size.get());
         log::info!("Spawning receiver task for channel id {channel_id}");
 
         let db = custom_data.db.clone();
         let claude = custom_data.claude.clone();
+        let rate_limiter = custom_data.rate_limiter.clone();
+        let default_api_key = custom_data.default_api_key.clone();
 
-        tokio::spawn(async move { handler_task(channel_id, db, claude, rx).await });
-
-        tx
+        custom_data
+            .shutdown
+            .spawn_handler_task(handler_task(
+                channel_id,
+                db,
+                claude,
+                rate_limiter,
+                default_api_key,
+                rx,
+            ))
+            .then(|| entry.insert(tx).value().clone())
     };
 
-    let tx = sender_entry()
-        .or_insert_with(tx_from_new_task)
-        .value()
-        .clone();
+    let tx = match sender_entry() {
+        Entry::Occupied(entry) => entry.get().clone(),
+        entry @ Entry::Vacant(_) => match tx_from_new_task(entry) {
+            Some(tx) => tx,
+            None => return Ok(()),
+        },
+    };
 
     if let Err(e) = tx.send(msg_ctx.clone()).await {
         log::warn!("Couldn't send message to channel id '{channel_id}' ({e})");
 
         log::info!("Restarting receiver task for channel id {channel_id}");
-        let new_tx = sender_entry().insert(tx_from_new_task()).value().clone();
+        let Some(new_tx) = tx_from_new_task(sender_entry()) else {
+            return Ok(());
+        };
 
         if let Err(e) = new_tx.send(msg_ctx).await {
             log::error!(
@@ -173,6 +386,16 @@ mod tests {
         let mut msg = MockMessageContext::new();
 
         msg.expect_mentioned().once().return_const(false);
+        msg.expect_origin().return_const(MessageOrigin::Created);
+
+        msg
+    }
+
+    fn edited_message(origin: MessageOrigin) -> MockMessageContext {
+        let mut msg = MockMessageContext::new();
+
+        msg.expect_mentioned().once().return_const(false);
+        msg.expect_origin().once().return_const(origin);
 
         msg
     }
@@ -189,6 +412,7 @@ mod tests {
         use super::*;
         use crate::discord::client::CustomData;
         use crate::discord::error_reply::ErrorReply;
+        use std::sync::Arc;
 
         #[tokio::test]
         async fn error_reply_if_mentioned_and_not_in_active_channel() {
@@ -208,16 +432,20 @@ mod tests {
                 &tempfile::NamedTempFile::new().unwrap().path().to_path_buf(),
             )
             .expect("db");
-            db.add_active_channel_id(server_id.into(), channel_id.into())
+            db.add_active_channel_id(server_id.into(), 0, channel_id.into())
                 .unwrap();
 
-            let claude = crate::claude::Client::new();
-            let channel_senders = dashmap::DashMap::new();
+            let claude = crate::claude::Client::default();
+            let channel_senders = Arc::default();
 
             let custom_data = CustomData {
                 db,
                 claude,
                 channel_senders,
+                rate_limiter: RateLimiter::default(),
+                default_api_key: DefaultApiKey::default(),
+                channel_queue_size: std::num::NonZeroUsize::new(128).unwrap(),
+                shutdown: Arc::default(),
             };
 
             assert!(handle_message(msg, &custom_data).await.is_ok());
@@ -253,6 +481,24 @@ mod tests {
             assert!(resp.is_none());
         }
 
+        #[test]
+        fn edit_without_mention_no_random_response() {
+            let msg = edited_message(MessageOrigin::Edited);
+
+            let resp = response_trigger(&msg, true);
+
+            assert!(resp.is_none());
+        }
+
+        #[test]
+        fn regenerate_triggers() {
+            let msg = edited_message(MessageOrigin::Regenerate);
+
+            let resp = response_trigger(&msg, false);
+
+            assert!(matches!(resp, Some(ResponseTrigger::Regenerate)));
+        }
+
         #[test]
         fn mention_takes_priority_over_random() {
             let msg = mentioned_message();
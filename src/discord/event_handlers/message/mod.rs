@@ -1,5 +1,9 @@
 mod action;
+mod delete;
+mod edit;
 mod handler;
 mod response_intent;
 
+pub use delete::{DeletedTriggerAction, handle_message_delete};
+pub use edit::handle_message_update;
 pub use handler::handle_message;
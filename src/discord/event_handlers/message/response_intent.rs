@@ -1,22 +1,45 @@
 use super::handler::ResponseTrigger;
 use crate::claude;
 use crate::database::Record;
-use crate::discord::MessageContext;
+use crate::discord::access;
 use crate::discord::error_reply::ErrorReply;
+use crate::discord::{ApiKey, MessageContext, RateLimiter};
+use std::time::{Duration, Instant};
 
 pub enum ResponseIntent<'a> {
     ShouldNotRespond,
     ErrorReplyWith(ErrorReply),
     ShouldRespondWith {
-        api_key: &'a str,
+        api_key: ApiKey<'a>,
         model: &'a claude::Model,
     },
 }
 
+fn acquire_rate_limit(
+    message: &impl MessageContext,
+    server_config: &Record,
+    rate_limiter: &RateLimiter,
+) -> Result<(), Duration> {
+    let Some(guild_id) = message.server_id() else {
+        return Ok(());
+    };
+
+    rate_limiter.acquire(
+        server_config,
+        guild_id,
+        message.channel_id(),
+        message.author_id(),
+        &message.author_role_ids(),
+        Instant::now(),
+    )
+}
+
 pub fn classify_response<'a>(
     trigger: &ResponseTrigger,
     message: &impl MessageContext,
     server_config: &'a Record,
+    api_key: Option<ApiKey<'a>>,
+    rate_limiter: &RateLimiter,
 ) -> ResponseIntent<'a> {
     if message.authored_by_bot() {
         return ResponseIntent::ShouldNotRespond;
@@ -24,6 +47,20 @@ pub fn classify_response<'a>(
 
     let mentioned = matches!(trigger, ResponseTrigger::Mention);
 
+    if access::is_restricted(server_config)
+        && !access::has_access(
+            server_config,
+            message.author_id(),
+            &message.author_role_ids(),
+        )
+    {
+        return if mentioned {
+            ResponseIntent::ErrorReplyWith(ErrorReply::AccessDenied)
+        } else {
+            ResponseIntent::ShouldNotRespond
+        };
+    }
+
     if message.is_reply() {
         return if mentioned {
             ResponseIntent::ErrorReplyWith(ErrorReply::CantSeeReplies)
@@ -32,7 +69,7 @@ pub fn classify_response<'a>(
         };
     }
 
-    let Some(api_key) = &server_config.claude_api_key else {
+    let Some(api_key) = api_key else {
         return if mentioned {
             ResponseIntent::ErrorReplyWith(ErrorReply::MissingAPIKey)
         } else {
@@ -40,6 +77,14 @@ pub fn classify_response<'a>(
         };
     };
 
+    if let Err(retry_after) = acquire_rate_limit(message, server_config, rate_limiter) {
+        return if mentioned {
+            ResponseIntent::ErrorReplyWith(ErrorReply::rate_limited(retry_after))
+        } else {
+            ResponseIntent::ShouldNotRespond
+        };
+    }
+
     ResponseIntent::ShouldRespondWith {
         api_key,
         model: &server_config.model,
@@ -52,8 +97,18 @@ mod tests {
     use super::ResponseIntent;
     use super::classify_response;
     use crate::database::Record;
-    use crate::discord::MockMessageContext;
+    use crate::discord::default_api_key::KeySource;
     use crate::discord::error_reply::ErrorReply;
+    use crate::discord::{ApiKey, MockMessageContext, RateLimit, RateLimiter};
+    use poise::serenity_prelude as serenity;
+    use std::num::NonZeroU32;
+
+    fn server_key(cfg: &Record) -> Option<ApiKey<'_>> {
+        cfg.claude_api_key.as_deref().map(|key| ApiKey {
+            key,
+            source: KeySource::Server,
+        })
+    }
 
     #[test]
     fn authored_by_bot_no_response() {
@@ -62,7 +117,13 @@ mod tests {
 
         msg.expect_authored_by_bot().once().return_const(true);
 
-        let res = classify_response(&ResponseTrigger::Mention, &msg, &cfg);
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg,
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
 
         assert!(matches!(res, ResponseIntent::ShouldNotRespond));
     }
@@ -75,7 +136,13 @@ mod tests {
         msg.expect_authored_by_bot().once().return_const(false);
         msg.expect_is_reply().once().return_const(true);
 
-        let res = classify_response(&ResponseTrigger::RandomChance, &msg, &cfg);
+        let res = classify_response(
+            &ResponseTrigger::RandomChance,
+            &msg,
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
 
         assert!(matches!(res, ResponseIntent::ShouldNotRespond));
     }
@@ -88,7 +155,13 @@ mod tests {
         msg.expect_authored_by_bot().once().return_const(false);
         msg.expect_is_reply().once().return_const(true);
 
-        let res = classify_response(&ResponseTrigger::Mention, &msg, &cfg);
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg,
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
 
         assert!(matches!(
             res,
@@ -104,7 +177,13 @@ mod tests {
         msg.expect_authored_by_bot().once().return_const(false);
         msg.expect_is_reply().once().return_const(false);
 
-        let res = classify_response(&ResponseTrigger::RandomChance, &msg, &cfg);
+        let res = classify_response(
+            &ResponseTrigger::RandomChance,
+            &msg,
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
 
         assert!(matches!(res, ResponseIntent::ShouldNotRespond));
     }
@@ -117,11 +196,117 @@ mod tests {
         msg.expect_authored_by_bot().once().return_const(false);
         msg.expect_is_reply().once().return_const(false);
 
-        let res = classify_response(&ResponseTrigger::Mention, &msg, &cfg);
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg,
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
 
         assert!(matches!(
             res,
             ResponseIntent::ErrorReplyWith(ErrorReply::MissingAPIKey)
         ));
     }
+
+    #[test]
+    fn rate_limited_mention_err_msg() {
+        let cfg = Record {
+            claude_api_key: Some("key".to_string()),
+            user_rate_limit: Some(RateLimit {
+                requests: NonZeroU32::new(1).unwrap(),
+                period_secs: NonZeroU32::new(60).unwrap(),
+            }),
+            ..Default::default()
+        };
+        let limiter = RateLimiter::default();
+
+        let msg = || {
+            let mut msg = MockMessageContext::new();
+            msg.expect_authored_by_bot().once().return_const(false);
+            msg.expect_is_reply().once().return_const(false);
+            msg.expect_server_id()
+                .once()
+                .return_const(serenity::GuildId::new(1));
+            msg.expect_channel_id()
+                .once()
+                .return_const(serenity::ChannelId::new(2));
+            msg.expect_author_id()
+                .once()
+                .return_const(serenity::UserId::new(3));
+            msg.expect_author_role_ids().once().returning(Vec::new);
+            msg
+        };
+
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg(),
+            &cfg,
+            server_key(&cfg),
+            &limiter,
+        );
+        assert!(matches!(res, ResponseIntent::ShouldRespondWith { .. }));
+
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg(),
+            &cfg,
+            server_key(&cfg),
+            &limiter,
+        );
+        assert!(matches!(
+            res,
+            ResponseIntent::ErrorReplyWith(ErrorReply::RateLimited(_))
+        ));
+
+        let res = classify_response(
+            &ResponseTrigger::RandomChance,
+            &msg(),
+            &cfg,
+            server_key(&cfg),
+            &limiter,
+        );
+        assert!(matches!(res, ResponseIntent::ShouldNotRespond));
+    }
+
+    #[test]
+    fn denied_user_no_response_or_err_msg() {
+        let cfg = Record {
+            claude_api_key: Some("key".to_string()),
+            denied_user_ids: [3].into(),
+            ..Default::default()
+        };
+
+        let msg = || {
+            let mut msg = MockMessageContext::new();
+            msg.expect_authored_by_bot().once().return_const(false);
+            msg.expect_author_id()
+                .once()
+                .return_const(serenity::UserId::new(3));
+            msg.expect_author_role_ids().once().returning(Vec::new);
+            msg
+        };
+
+        let res = classify_response(
+            &ResponseTrigger::RandomChance,
+            &msg(),
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
+        assert!(matches!(res, ResponseIntent::ShouldNotRespond));
+
+        let res = classify_response(
+            &ResponseTrigger::Mention,
+            &msg(),
+            &cfg,
+            server_key(&cfg),
+            &RateLimiter::default(),
+        );
+        assert!(matches!(
+            res,
+            ResponseIntent::ErrorReplyWith(ErrorReply::AccessDenied)
+        ));
+    }
 }
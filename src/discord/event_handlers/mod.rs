@@ -1,10 +1,14 @@
-use super::SerenityMessageContext;
+use super::{MessageOrigin, SerenityMessageContext};
 use crate::discord::CommandError;
 use crate::discord::client::CustomData;
 use poise::serenity_prelude as serenity;
 
+pub(super) mod component;
+pub(super) mod feedback;
 mod message;
 
+pub use message::DeletedTriggerAction;
+
 pub async fn handle_event(
     ctx: &serenity::Context,
     event: &serenity::FullEvent,
@@ -20,11 +24,67 @@ pub async fn handle_event(
                 SerenityMessageContext {
                     context: ctx.clone(),
                     message: new_message.clone(),
+                    origin: MessageOrigin::Created,
                 },
                 custom_data,
             )
             .await?;
         }
+        serenity::FullEvent::MessageUpdate {
+            old_if_available,
+            new,
+            event,
+        } => {
+            message::handle_message_update(
+                ctx,
+                old_if_available.as_ref(),
+                new.as_ref(),
+                event,
+                custom_data,
+            )
+            .await?;
+        }
+        serenity::FullEvent::MessageDelete {
+            channel_id,
+            deleted_message_id,
+            guild_id,
+        } => {
+            message::handle_message_delete(
+                ctx,
+                *channel_id,
+                *deleted_message_id,
+                *guild_id,
+                custom_data,
+            )
+            .await?;
+        }
+        serenity::FullEvent::MessageDeleteBulk {
+            channel_id,
+            multiple_deleted_messages_ids,
+            guild_id,
+        } => {
+            for deleted_message_id in multiple_deleted_messages_ids {
+                message::handle_message_delete(
+                    ctx,
+                    *channel_id,
+                    *deleted_message_id,
+                    *guild_id,
+                    custom_data,
+                )
+                .await?;
+            }
+        }
+        serenity::FullEvent::ReactionAdd { add_reaction } => {
+            feedback::handle_reaction(ctx, add_reaction, true, custom_data)?;
+        }
+        serenity::FullEvent::ReactionRemove { removed_reaction } => {
+            feedback::handle_reaction(ctx, removed_reaction, false, custom_data)?;
+        }
+        serenity::FullEvent::InteractionCreate { interaction } => {
+            if let Some(component) = interaction.as_message_component() {
+                component::handle_component(ctx, component, custom_data).await?;
+            }
+        }
         _ => {}
     }
     Ok(())
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use poise::serenity_prelude as serenity;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use super::SerenityMessageContext;
+use super::client::ChannelSenders;
+use super::shutdown::Shutdown;
+use crate::metrics;
+
+type ShardRunners = Arc<Mutex<HashMap<serenity::ShardId, serenity::ShardRunnerInfo>>>;
+
+/// What `/healthz` and `/readyz` report
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub database_readable: bool,
+    pub commands_registered: bool,
+    pub shutting_down: bool,
+    pub shards: Vec<ShardStatus>,
+    /// RFC 3339 time the last successful request to Claude finished
+    pub last_claude_success: Option<String>,
+    /// Channels whose task exited without being replaced, e.g. after a panic.
+    /// They're restarted when the channel's next message arrives.
+    pub dead_channel_tasks: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShardStatus {
+    pub id: u32,
+    /// e.g. `connected`, `resuming` or `disconnected`
+    pub stage: String,
+    pub connected: bool,
+    pub latency_ms: Option<u128>,
+}
+
+impl HealthReport {
+    /// Whether the process is alive and can read its database
+    pub fn is_healthy(&self) -> bool {
+        self.database_readable
+    }
+
+    /// Whether the bot can take messages and commands, meaning every shard is
+    /// connected to the gateway and the slash commands are registered
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy()
+            && self.commands_registered
+            && !self.shutting_down
+            && !self.shards.is_empty()
+            && self.shards.iter().all(|shard| shard.connected)
+    }
+}
+
+/// Everything a `HealthReport` is put together from
+#[derive(Clone)]
+pub struct HealthCheck {
+    pub db: crate::database::Client,
+    pub shard_runners: ShardRunners,
+    pub channel_senders: ChannelSenders<SerenityMessageContext>,
+    pub commands_registered: Arc<AtomicBool>,
+    pub shutdown: Arc<Shutdown>,
+}
+
+impl HealthCheck {
+    pub async fn report(&self) -> HealthReport {
+        let database_readable = match self.db.verify_readable() {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Health check couldn't read the database ({e})");
+                false
+            }
+        };
+
+        let mut shards = self
+            .shard_runners
+            .lock()
+            .await
+            .iter()
+            .map(|(id, runner)| ShardStatus {
+                id: id.0,
+                stage: runner.stage.to_string(),
+                connected: runner.stage == serenity::ConnectionStage::Connected,
+                latency_ms: runner.latency.map(|latency| latency.as_millis()),
+            })
+            .collect::<Vec<_>>();
+        shards.sort_by_key(|shard| shard.id);
+
+        HealthReport {
+            database_readable,
+            commands_registered: self.commands_registered.load(Ordering::Acquire),
+            shutting_down: self.shutdown.is_started(),
+            shards,
+            last_claude_success: metrics::last_claude_success().map(|time| time.to_rfc3339()),
+            dead_channel_tasks: self
+                .channel_senders
+                .iter()
+                .filter(|entry| entry.value().is_closed())
+                .count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HealthReport, ShardStatus};
+
+    fn shard(id: u32, connected: bool) -> ShardStatus {
+        ShardStatus {
+            id,
+            stage: if connected { "connected" } else { "resuming" }.to_string(),
+            connected,
+            latency_ms: None,
+        }
+    }
+
+    #[test]
+    fn ready_once_every_shard_connected_and_commands_registered() {
+        let mut report = HealthReport {
+            database_readable: true,
+            commands_registered: false,
+            shutting_down: false,
+            shards: vec![],
+            last_claude_success: None,
+            dead_channel_tasks: 0,
+        };
+        assert!(report.is_healthy());
+        assert!(!report.is_ready());
+
+        report.commands_registered = true;
+        report.shards = vec![shard(0, true), shard(1, false)];
+        assert!(!report.is_ready());
+
+        report.shards[1] = shard(1, true);
+        assert!(report.is_ready());
+
+        report.shutting_down = true;
+        assert!(!report.is_ready());
+
+        report.database_readable = false;
+        assert!(!report.is_healthy());
+    }
+}
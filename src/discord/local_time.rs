@@ -0,0 +1,76 @@
+use chrono::{DateTime, Locale, Utc};
+use chrono_tz::Tz;
+
+/// Formats timestamps in a server's configured timezone and locale, falling
+/// back to the host's timezone and `en_US`
+#[derive(Clone, Copy, Debug)]
+pub struct LocalTime {
+    timezone: Option<Tz>,
+    locale: Locale,
+}
+
+impl LocalTime {
+    pub fn new(timezone: Option<&str>, locale: Option<&str>) -> Self {
+        Self {
+            timezone: timezone.and_then(|tz| tz.parse().ok()),
+            locale: locale.and_then(|l| l.parse().ok()).unwrap_or(Locale::en_US),
+        }
+    }
+
+    pub fn format(self, time: DateTime<Utc>, fmt: &str) -> String {
+        match self.timezone {
+            Some(tz) => time
+                .with_timezone(&tz)
+                .format_localized(fmt, self.locale)
+                .to_string(),
+            None => time
+                .with_timezone(&chrono::Local)
+                .format_localized(fmt, self.locale)
+                .to_string(),
+        }
+    }
+
+    /// The current date and time, along with the timezone it's in
+    pub fn now(self) -> String {
+        let now = self.format(Utc::now(), "%A, %B %-d, %Y %-I:%M%p");
+
+        match self.timezone {
+            Some(tz) => format!("{now} ({tz})"),
+            None => now,
+        }
+    }
+}
+
+impl Default for LocalTime {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalTime;
+    use chrono::DateTime;
+
+    #[test]
+    fn formats_in_timezone() {
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let local = LocalTime::new(Some("America/New_York"), None);
+
+        assert_eq!(
+            local.format(time, "%-m-%-d-%Y %-I:%M%p"),
+            "11-14-2023 5:13PM"
+        );
+    }
+
+    #[test]
+    fn formats_in_locale() {
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let local = LocalTime::new(Some("Europe/Berlin"), Some("de_DE"));
+
+        assert_eq!(
+            local.format(time, "%A %-d %B %H:%M"),
+            "Dienstag 14 November 23:13"
+        );
+    }
+}
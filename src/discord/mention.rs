@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+
+use bincode::{Decode, Encode};
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+/// Who Claude's messages are allowed to ping
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode, ChoiceParameter,
+)]
+pub enum MentionPolicy {
+    #[name = "Nobody"]
+    Nobody,
+    #[name = "Users"]
+    #[default]
+    Users,
+    #[name = "Users and roles"]
+    UsersAndRoles,
+    #[name = "Everyone"]
+    Everyone,
+}
+
+impl MentionPolicy {
+    pub fn allowed_mentions(&self) -> serenity::CreateAllowedMentions {
+        let (users, roles, everyone) = match self {
+            MentionPolicy::Nobody => (false, false, false),
+            MentionPolicy::Users => (true, false, false),
+            MentionPolicy::UsersAndRoles => (true, true, false),
+            MentionPolicy::Everyone => (true, true, true),
+        };
+
+        serenity::CreateAllowedMentions::new()
+            .all_users(users)
+            .all_roles(roles)
+            .everyone(everyone)
+    }
+}
+
+impl Display for MentionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Turns the `@name` text Claude writes back into Discord mentions, and
+/// restricts what those mentions are allowed to ping
+#[derive(Clone, Debug, Default)]
+pub struct OutgoingMentions {
+    participants: HashMap<String, serenity::UserId>,
+    policy: MentionPolicy,
+}
+
+impl OutgoingMentions {
+    /// Collects the names of every author and mentioned user in `history`
+    pub fn new(history: &[serenity::Message], policy: MentionPolicy) -> Self {
+        let mut participants = HashMap::new();
+
+        for msg in history {
+            let nick = msg.member.as_ref().and_then(|m| m.nick.as_deref());
+            for name in Self::names(&msg.author).chain(nick) {
+                participants.insert(name.to_lowercase(), msg.author.id);
+            }
+
+            for user in &msg.mentions {
+                let nick = user.member.as_ref().and_then(|m| m.nick.as_deref());
+                for name in Self::names(user).chain(nick) {
+                    participants.insert(name.to_lowercase(), user.id);
+                }
+            }
+        }
+
+        Self {
+            participants,
+            policy,
+        }
+    }
+
+    fn names(user: &serenity::User) -> impl Iterator<Item = &str> {
+        std::iter::once(user.name.as_str()).chain(user.global_name.as_deref())
+    }
+
+    pub fn create_message(&self, text: &str) -> serenity::CreateMessage {
+        serenity::CreateMessage::new()
+            .content(self.mentionify(text))
+            .allowed_mentions(self.allowed_mentions())
+    }
+
+    pub fn allowed_mentions(&self) -> serenity::CreateAllowedMentions {
+        self.policy.allowed_mentions()
+    }
+
+    /// Replaces `@name` with a mention of the participant named `name`,
+    /// preferring the longest matching name
+    pub fn mentionify(&self, text: &str) -> String {
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+
+        let mut mentionified = String::with_capacity(text.len());
+        let mut rest = text;
+        let mut prev: Option<char> = None;
+
+        while let Some(at) = rest.find('@') {
+            let before = &rest[..at];
+            mentionified.push_str(before);
+            prev = before.chars().next_back().or(prev);
+
+            let after = &rest[at + 1..];
+            let matched = if prev.is_some_and(is_name_char) {
+                None
+            } else {
+                self.participants
+                    .iter()
+                    .filter_map(|(name, id)| {
+                        let after_name = strip_name(after, name)?;
+                        (!after_name.starts_with(is_name_char)).then_some((name, id, after_name))
+                    })
+                    .max_by_key(|(name, ..)| name.len())
+            };
+
+            if let Some((_, id, after_name)) = matched {
+                write!(mentionified, "<@{id}>").unwrap();
+                rest = after_name;
+                prev = Some('>');
+            } else {
+                mentionified.push('@');
+                rest = after;
+                prev = Some('@');
+            }
+        }
+
+        mentionified.push_str(rest);
+        mentionified
+    }
+}
+
+/// What follows `name` at the start of `text`, which is compared to the
+/// lowercase `name` a character at a time, since lowercasing can change how
+/// many bytes a character takes
+fn strip_name<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let mut expected = name.chars().peekable();
+    let mut end = 0;
+
+    for (i, c) in text.char_indices() {
+        if expected.peek().is_none() {
+            break;
+        }
+        for lower in c.to_lowercase() {
+            if expected.next() != Some(lower) {
+                return None;
+            }
+        }
+        end = i + c.len_utf8();
+    }
+
+    expected.peek().is_none().then(|| &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MentionPolicy, OutgoingMentions};
+    use poise::serenity_prelude as serenity;
+
+    fn mentions(names: &[(&str, u64)]) -> OutgoingMentions {
+        OutgoingMentions {
+            participants: names
+                .iter()
+                .map(|&(name, id)| (name.to_string(), serenity::UserId::new(id)))
+                .collect(),
+            policy: MentionPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn known_name_becomes_mention() {
+        let m = mentions(&[("alice", 1)]);
+
+        assert_eq!(m.mentionify("hi @Alice!"), "hi <@1>!");
+    }
+
+    #[test]
+    fn unknown_name_unchanged() {
+        let m = mentions(&[("alice", 1)]);
+
+        assert_eq!(m.mentionify("@everyone and @bob"), "@everyone and @bob");
+    }
+
+    #[test]
+    fn names_changing_length_when_lowercased_matched() {
+        let m = mentions(&[("i\u{307}pek", 1), ("åsa", 2)]);
+
+        assert_eq!(m.mentionify("@İpek and @\u{212B}SA!"), "<@1> and <@2>!");
+    }
+
+    #[test]
+    fn longest_name_wins() {
+        let m = mentions(&[("al", 1), ("al capone", 2)]);
+
+        assert_eq!(m.mentionify("@al capone and @al"), "<@2> and <@1>");
+    }
+
+    #[test]
+    fn partial_names_and_emails_unchanged() {
+        let m = mentions(&[("al", 1)]);
+
+        assert_eq!(m.mentionify("@alice me@al"), "@alice me@al");
+    }
+}
@@ -1,16 +1,332 @@
+use chrono::{DateTime, TimeDelta, Utc};
 use poise::serenity_prelude as serenity;
 
+use super::LocalTime;
+
 pub trait NormalizeContent {
-    fn normalize_content(&self) -> String;
+    fn normalize_content(&self, cache: &serenity::Cache, local_time: LocalTime) -> String;
+}
+
+/// Discord markup that needs outside context to be made readable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mention {
+    User(serenity::UserId),
+    Channel(serenity::ChannelId),
+    Role(serenity::RoleId),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Markup<'a> {
+    Mention(Mention),
+    CustomEmoji(&'a str),
+    Timestamp(DateTime<Utc>, Option<char>),
 }
 
 impl NormalizeContent for serenity::Message {
-    fn normalize_content(&self) -> String {
-        self.mentions.iter().fold(self.content.clone(), |acc, u| {
-            acc.replace(
-                format!("<@{}>", u.id).as_str(),
-                format!("@{}", u.display_name()).as_str(),
-            )
-        })
+    fn normalize_content(&self, cache: &serenity::Cache, local_time: LocalTime) -> String {
+        let guild = self.guild_id.and_then(|id| cache.guild(id));
+
+        normalize(
+            &self.content,
+            Utc::now(),
+            local_time,
+            |mention| match mention {
+                Mention::User(id) => self
+                    .mentions
+                    .iter()
+                    .find(|u| u.id == id)
+                    .map(|u| {
+                        u.member
+                            .as_ref()
+                            .and_then(|m| m.nick.clone())
+                            .unwrap_or_else(|| u.display_name().to_string())
+                    })
+                    .or_else(|| cache.user(id).map(|u| u.display_name().to_string()))
+                    .map(|name| format!("@{name}")),
+                Mention::Channel(id) => guild.as_ref().and_then(|g| {
+                    g.channels
+                        .get(&id)
+                        .map(|c| c.name.clone())
+                        .or_else(|| {
+                            g.threads
+                                .iter()
+                                .find(|t| t.id == id)
+                                .map(|t| t.name.clone())
+                        })
+                        .map(|name| format!("#{name}"))
+                }),
+                Mention::Role(id) if self.guild_id.is_some_and(|g| g.get() == id.get()) => {
+                    Some(String::from("@everyone"))
+                }
+                Mention::Role(id) => guild
+                    .as_ref()
+                    .and_then(|g| g.roles.get(&id))
+                    .map(|r| format!("@{}", r.name)),
+            },
+        )
+    }
+}
+
+/// Replaces Discord markup in `content` with readable text. Mentions are
+/// resolved with `resolve_mention`, and are left untouched if it can't
+/// resolve them.
+fn normalize(
+    content: &str,
+    now: DateTime<Utc>,
+    local_time: LocalTime,
+    resolve_mention: impl Fn(Mention) -> Option<String>,
+) -> String {
+    let mut normalized = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find('<') {
+        normalized.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+
+        let replacement = parse_markup(&rest[1..end]).and_then(|markup| match markup {
+            Markup::Mention(mention) => resolve_mention(mention),
+            Markup::CustomEmoji(name) => Some(format!(":{name}:")),
+            Markup::Timestamp(time, style) => Some(format_timestamp(time, style, now, local_time)),
+        });
+
+        if let Some(text) = replacement {
+            normalized.push_str(&text);
+            rest = &rest[end + 1..];
+        } else {
+            normalized.push('<');
+            rest = &rest[1..];
+        }
+    }
+
+    normalized.push_str(rest);
+    normalized
+}
+
+fn parse_id(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    s.parse().ok().filter(|&id| id != 0)
+}
+
+fn parse_markup(inner: &str) -> Option<Markup<'_>> {
+    if let Some(id) = inner.strip_prefix("@&") {
+        return parse_id(id).map(|id| Markup::Mention(Mention::Role(id.into())));
+    }
+
+    if let Some(id) = inner.strip_prefix("@!").or_else(|| inner.strip_prefix('@')) {
+        return parse_id(id).map(|id| Markup::Mention(Mention::User(id.into())));
+    }
+
+    if let Some(id) = inner.strip_prefix('#') {
+        return parse_id(id).map(|id| Markup::Mention(Mention::Channel(id.into())));
+    }
+
+    if let Some(timestamp) = inner.strip_prefix("t:") {
+        let (secs, style) = match timestamp.split_once(':') {
+            Some((secs, style)) => {
+                let mut chars = style.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c @ ('t' | 'T' | 'd' | 'D' | 'f' | 'F' | 'R')), None) => (secs, Some(c)),
+                    _ => return None,
+                }
+            }
+            None => (timestamp, None),
+        };
+
+        let secs = secs.parse::<i64>().ok()?;
+
+        return DateTime::from_timestamp(secs, 0).map(|time| Markup::Timestamp(time, style));
+    }
+
+    let emoji = inner
+        .strip_prefix("a:")
+        .or_else(|| inner.strip_prefix(':'))?;
+    let (name, id) = emoji.split_once(':')?;
+    parse_id(id)?;
+
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    Some(Markup::CustomEmoji(name))
+}
+
+fn format_timestamp(
+    time: DateTime<Utc>,
+    style: Option<char>,
+    now: DateTime<Utc>,
+    local_time: LocalTime,
+) -> String {
+    match style {
+        Some('t') => local_time.format(time, "%-I:%M%p"),
+        Some('T') => local_time.format(time, "%-I:%M:%S%p"),
+        Some('d') => local_time.format(time, "%-m-%-d-%Y"),
+        Some('D') => local_time.format(time, "%B %-d, %Y"),
+        Some('F') => local_time.format(time, "%A, %B %-d, %Y %-I:%M%p"),
+        Some('R') => format_relative(time - now),
+        _ => local_time.format(time, "%B %-d, %Y %-I:%M%p"),
+    }
+}
+
+fn format_relative(delta: TimeDelta) -> String {
+    let secs = delta.num_seconds().unsigned_abs();
+
+    let (amount, unit) = match secs {
+        0..60 => return String::from("just now"),
+        60..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        86_400..2_592_000 => (secs / 86_400, "day"),
+        2_592_000..31_536_000 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+
+    let plural = if amount == 1 { "" } else { "s" };
+
+    if delta < TimeDelta::zero() {
+        format!("{amount} {unit}{plural} ago")
+    } else {
+        format!("in {amount} {unit}{plural}")
+    }
+}
+
+/// Most characters Discord allows in a message
+pub const MESSAGE_LIMIT: usize = 2000;
+
+/// Splits `text` into pieces of at most `limit` bytes, preferring to break at
+/// newlines, then at whitespace
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut rest = text.trim();
+
+    while rest.len() > limit {
+        let mut end = limit;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let head = &rest[..end];
+        let split = head
+            .rfind('\n')
+            .or_else(|| head.rfind(char::is_whitespace))
+            .filter(|&i| i > 0)
+            .unwrap_or(end);
+
+        pieces.push(rest[..split].trim_end().to_string());
+        rest = rest[split..].trim_start();
+    }
+
+    if !rest.is_empty() {
+        pieces.push(rest.to_string());
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mention, normalize, split_message};
+    use crate::discord::LocalTime;
+    use chrono::{DateTime, Utc};
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn resolve(mention: Mention) -> Option<String> {
+        match mention {
+            Mention::User(id) if id.get() == 1 => Some(String::from("@alice")),
+            Mention::Channel(id) if id.get() == 2 => Some(String::from("#general")),
+            Mention::Role(id) if id.get() == 3 => Some(String::from("@mods")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn plain_text_unchanged() {
+        let content = "hello <world> 1 < 2 > 0";
+
+        assert_eq!(
+            normalize(content, now(), LocalTime::default(), resolve),
+            content
+        );
+    }
+
+    #[test]
+    fn user_mentions() {
+        assert_eq!(
+            normalize("hi <@1> and <@!1>", now(), LocalTime::default(), resolve),
+            "hi @alice and @alice"
+        );
+    }
+
+    #[test]
+    fn channel_and_role_mentions() {
+        assert_eq!(
+            normalize("<@&3> see <#2>", now(), LocalTime::default(), resolve),
+            "@mods see #general"
+        );
+    }
+
+    #[test]
+    fn unresolved_mentions_unchanged() {
+        let content = "<@4> <#5> <@&6>";
+
+        assert_eq!(
+            normalize(content, now(), LocalTime::default(), resolve),
+            content
+        );
+    }
+
+    #[test]
+    fn custom_emoji() {
+        assert_eq!(
+            normalize(
+                "nice <:pepe:1234> <a:party_blob:5678>",
+                now(),
+                LocalTime::default(),
+                resolve
+            ),
+            "nice :pepe: :party_blob:"
+        );
+    }
+
+    #[test]
+    fn relative_timestamps() {
+        assert_eq!(
+            normalize(
+                "<t:1700007200:R> <t:1699913600:R>",
+                now(),
+                LocalTime::default(),
+                resolve
+            ),
+            "in 2 hours 1 day ago"
+        );
+    }
+
+    #[test]
+    fn malformed_timestamps_unchanged() {
+        let content = "<t:abc> <t:1700000000:X>";
+
+        assert_eq!(
+            normalize(content, now(), LocalTime::default(), resolve),
+            content
+        );
+    }
+
+    #[test]
+    fn split_prefers_newlines_then_whitespace() {
+        assert_eq!(split_message("short", 10), vec!["short"]);
+        assert_eq!(
+            split_message("one two\nthree four", 12),
+            vec!["one two", "three four"]
+        );
+        assert_eq!(split_message("one two three", 9), vec!["one two", "three"]);
+        assert_eq!(split_message("abcdefgh", 3), vec!["abc", "def", "gh"]);
     }
 }
@@ -3,14 +3,27 @@ use itertools::Itertools;
 use mockall::{automock, predicate::*};
 
 use crate::claude;
+use crate::discord::LocalTime;
 use crate::discord::error_reply::ErrorReply;
+use crate::metrics;
 use crate::{database::Record, discord::CommandError};
 use poise::serenity_prelude::{self as serenity, GetMessages, async_trait};
 
+/// What caused a message to be handed to the bot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageOrigin {
+    Created,
+    /// An edit that newly mentions the bot
+    Edited,
+    /// An edit to a message the bot already responded to
+    Regenerate,
+}
+
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait MessageContext: Clone + Sync + Send {
     fn into_inner(self) -> (serenity::Context, serenity::Message);
+    fn origin(&self) -> MessageOrigin;
     fn authored_by_bot(&self) -> bool;
     fn is_reply(&self) -> bool;
     fn mentioned(&self) -> bool;
@@ -19,16 +32,28 @@ pub trait MessageContext: Clone + Sync + Send {
     fn content(&self) -> &str;
     fn server_id(&self) -> Option<serenity::GuildId>;
     fn channel_id(&self) -> serenity::ChannelId;
-    async fn message_history(&self) -> Result<Vec<serenity::Message>, CommandError>;
+    fn message_id(&self) -> serenity::MessageId;
+    fn author_id(&self) -> serenity::UserId;
+    fn author_role_ids(&self) -> Vec<serenity::RoleId>;
+    /// The message and up to `length - 1` messages before it
+    async fn message_history(&self, length: u8) -> Result<Vec<serenity::Message>, CommandError>;
 
     async fn error_reply(&self, reply: ErrorReply) -> Result<(), CommandError>;
-    async fn get_claude_messages(&self) -> Result<Vec<claude::Message>, CommandError>;
+    /// Deletes messages in this message's channel, logging any that couldn't
+    /// be
+    async fn delete_messages(&self, ids: Vec<serenity::MessageId>);
+    fn claude_messages(
+        &self,
+        history: &[serenity::Message],
+        local_time: LocalTime,
+    ) -> Vec<claude::Message>;
 }
 
 #[derive(Clone)]
 pub struct SerenityMessageContext {
     pub context: serenity::Context,
     pub message: serenity::Message,
+    pub origin: MessageOrigin,
 }
 
 #[cfg(test)]
@@ -44,6 +69,10 @@ impl MessageContext for SerenityMessageContext {
         (self.context, self.message)
     }
 
+    fn origin(&self) -> MessageOrigin {
+        self.origin
+    }
+
     fn authored_by_bot(&self) -> bool {
         self.message.author.id == self.context.cache.current_user().id
     }
@@ -80,15 +109,29 @@ impl MessageContext for SerenityMessageContext {
         self.message.channel_id
     }
 
-    async fn message_history(&self) -> Result<Vec<serenity::Message>, CommandError> {
+    fn message_id(&self) -> serenity::MessageId {
+        self.message.id
+    }
+
+    fn author_id(&self) -> serenity::UserId {
+        self.message.author.id
+    }
+
+    fn author_role_ids(&self) -> Vec<serenity::RoleId> {
+        self.message
+            .member
+            .as_ref()
+            .map(|m| m.roles.clone())
+            .unwrap_or_default()
+    }
+
+    async fn message_history(&self, length: u8) -> Result<Vec<serenity::Message>, CommandError> {
         Ok(std::iter::once(self.message.clone())
             .chain(
                 self.channel_id()
                     .messages(
                         &self.context,
-                        GetMessages::new()
-                            .before(self.message.id)
-                            .limit(claude::MESSAGE_CONTEXT_LENGTH - 1),
+                        GetMessages::new().before(self.message.id).limit(length - 1),
                     )
                     .await?,
             )
@@ -97,19 +140,34 @@ impl MessageContext for SerenityMessageContext {
     }
 
     async fn error_reply(&self, reply: ErrorReply) -> Result<(), CommandError> {
+        metrics::record_error_reply(reply.kind());
+
         Ok(self
             .message
             .reply(&self.context, reply.pretty_str())
             .await
+            .inspect_err(|_| metrics::record_send_failure("reply"))
             .map(|_| ())?)
     }
 
-    async fn get_claude_messages(&self) -> Result<Vec<claude::Message>, CommandError> {
-        Ok(self
-            .message_history()
-            .await?
+    async fn delete_messages(&self, ids: Vec<serenity::MessageId>) {
+        let channel_id = self.channel_id();
+
+        for id in ids {
+            if let Err(e) = channel_id.delete_message(&self.context, id).await {
+                log::warn!("Couldn't delete message {id} in channel id {channel_id} ({e})");
+            }
+        }
+    }
+
+    fn claude_messages(
+        &self,
+        history: &[serenity::Message],
+        local_time: LocalTime,
+    ) -> Vec<claude::Message> {
+        history
             .iter()
-            .flat_map(|m| claude::Message::from(m, &self.context))
-            .collect_vec())
+            .flat_map(|m| claude::Message::from(m, &self.context, local_time))
+            .collect_vec()
     }
 }
@@ -1,13 +1,32 @@
+mod access;
+mod api_key;
+mod ask;
+mod audit_log;
 mod client;
 mod command;
+mod default_api_key;
 mod error_reply;
 mod event_handlers;
+mod health;
+mod local_time;
+mod mention;
 mod message;
 mod message_context;
+mod permission;
+mod rate_limit;
+mod shutdown;
+mod summary;
 
+pub use access::Access;
 pub use client::Bot;
+pub use default_api_key::{ApiKey, DefaultApiKey};
+pub use event_handlers::DeletedTriggerAction;
+pub use local_time::LocalTime;
+pub use mention::{MentionPolicy, OutgoingMentions};
 pub use message::NormalizeContent;
-pub use message_context::{MessageContext, SerenityMessageContext};
+pub use message_context::{MessageContext, MessageOrigin, SerenityMessageContext};
+pub use permission::ManagerPermission;
+pub use rate_limit::{RateLimit, RateLimitScope, RateLimiter};
 
 #[cfg(test)]
 pub use message_context::MockMessageContext;
@@ -15,3 +34,5 @@ pub use message_context::MockMessageContext;
 type CommandError = Box<dyn std::error::Error + Send + Sync>;
 type PoiseContext<'a> =
     poise::Context<'a, client::CustomData<SerenityMessageContext>, CommandError>;
+type PoiseApplicationContext<'a> =
+    poise::ApplicationContext<'a, client::CustomData<SerenityMessageContext>, CommandError>;
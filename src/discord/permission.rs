@@ -0,0 +1,153 @@
+use std::fmt::Display;
+
+use bincode::{Decode, Encode};
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+use crate::database::Record;
+use crate::discord::{CommandError, PoiseContext};
+
+/// What members with the bot manager role may configure
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Encode,
+    Decode,
+    ChoiceParameter,
+)]
+pub enum ManagerPermission {
+    #[name = "API key"]
+    ApiKey,
+    #[name = "Model"]
+    Model,
+    #[name = "Active channels"]
+    Channels,
+    #[name = "Behavior"]
+    Behavior,
+    #[name = "Access and rate limits"]
+    Limits,
+    #[name = "Feedback export"]
+    Feedback,
+}
+
+impl Display for ManagerPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Whether a member can change the part of the config covered by `permission`
+fn is_allowed(
+    server_config: &Record,
+    is_admin: bool,
+    role_ids: &[serenity::RoleId],
+    permission: ManagerPermission,
+) -> bool {
+    let is_manager = server_config
+        .bot_manager_role_id
+        .is_some_and(|id| role_ids.contains(&serenity::RoleId::new(id)));
+
+    is_admin || (is_manager && server_config.bot_manager_permissions.contains(&permission))
+}
+
+async fn can_manage(
+    ctx: PoiseContext<'_>,
+    permission: ManagerPermission,
+) -> Result<bool, CommandError> {
+    let (Some(guild_id), Some(member)) = (ctx.guild_id(), ctx.author_member().await) else {
+        return Ok(false);
+    };
+
+    let is_admin = member
+        .permissions
+        .is_some_and(serenity::Permissions::administrator);
+    let server_config = ctx.data().db.get_config(guild_id.get())?;
+
+    if is_allowed(&server_config, is_admin, &member.roles, permission) {
+        return Ok(true);
+    }
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "*You need to be an administrator, or a bot manager with the '{permission}' permission, to do that*"
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(false)
+}
+
+pub async fn can_manage_api_key(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::ApiKey).await
+}
+
+pub async fn can_manage_model(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::Model).await
+}
+
+pub async fn can_manage_channels(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::Channels).await
+}
+
+pub async fn can_manage_behavior(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::Behavior).await
+}
+
+pub async fn can_manage_limits(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::Limits).await
+}
+
+pub async fn can_manage_feedback(ctx: PoiseContext<'_>) -> Result<bool, CommandError> {
+    can_manage(ctx, ManagerPermission::Feedback).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManagerPermission, is_allowed};
+    use crate::database::Record;
+    use poise::serenity_prelude as serenity;
+
+    #[test]
+    fn admins_always_allowed() {
+        let cfg = Record::default();
+
+        assert!(is_allowed(&cfg, true, &[], ManagerPermission::ApiKey));
+        assert!(!is_allowed(&cfg, false, &[], ManagerPermission::ApiKey));
+    }
+
+    #[test]
+    fn managers_limited_to_granted_permissions() {
+        let cfg = Record {
+            bot_manager_role_id: Some(10),
+            bot_manager_permissions: [ManagerPermission::Model, ManagerPermission::Channels].into(),
+            ..Default::default()
+        };
+        let manager = [serenity::RoleId::new(10)];
+        let other = [serenity::RoleId::new(11)];
+
+        assert!(is_allowed(&cfg, false, &manager, ManagerPermission::Model));
+        assert!(is_allowed(
+            &cfg,
+            false,
+            &manager,
+            ManagerPermission::Channels
+        ));
+        assert!(!is_allowed(
+            &cfg,
+            false,
+            &manager,
+            ManagerPermission::ApiKey
+        ));
+        assert!(!is_allowed(&cfg, false, &other, ManagerPermission::Model));
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt::Display;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bincode::{Decode, Encode};
+use dashmap::DashMap;
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+use crate::database::Record;
+
+/// A token bucket that holds up to `requests` tokens and refills completely
+/// every `period_secs` seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct RateLimit {
+    pub requests: NonZeroU32,
+    pub period_secs: NonZeroU32,
+}
+
+impl RateLimit {
+    fn tokens_per_sec(self) -> f64 {
+        f64::from(self.requests.get()) / f64::from(self.period_secs.get())
+    }
+}
+
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} per {}s", self.requests, self.period_secs)
+    }
+}
+
+/// What a rate limit is counted against
+#[derive(Clone, Copy, Debug, ChoiceParameter)]
+pub enum RateLimitScope {
+    #[name = "Per user"]
+    User,
+    #[name = "Per channel"]
+    Channel,
+    #[name = "Server-wide"]
+    Guild,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(serenity::GuildId, serenity::UserId),
+    Channel(serenity::ChannelId),
+    Guild(serenity::GuildId),
+}
+
+/// How often buckets that have refilled completely are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_mins(10);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The limit the bucket was last refilled with
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        self.tokens = self.tokens_at(limit, now);
+        self.updated = now;
+        self.limit = limit;
+    }
+
+    fn tokens_at(&self, limit: RateLimit, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        (self.tokens + elapsed * limit.tokens_per_sec()).min(f64::from(limit.requests.get()))
+    }
+
+    /// Whether the bucket is as full as a new one would be
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(self.limit, now) >= f64::from(self.limit.requests.get())
+    }
+}
+
+/// Token buckets for every user, channel, and server that's asked Claude for
+/// something recently
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<DashMap<BucketKey, Bucket>>,
+    last_pruned: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from each of the buckets the request counts against, or
+    /// returns how long until all of them have one. Members with an exempt
+    /// role aren't limited.
+    pub fn acquire(
+        &self,
+        server_config: &Record,
+        guild_id: serenity::GuildId,
+        channel_id: serenity::ChannelId,
+        user_id: serenity::UserId,
+        role_ids: &[serenity::RoleId],
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.prune(now);
+
+        if role_ids
+            .iter()
+            .any(|id| server_config.rate_limit_exempt_role_ids.contains(&id.get()))
+        {
+            return Ok(());
+        }
+
+        let limits = [
+            (
+                BucketKey::User(guild_id, user_id),
+                server_config.user_rate_limit,
+            ),
+            (
+                BucketKey::Channel(channel_id),
+                server_config.channel_rate_limit,
+            ),
+            (BucketKey::Guild(guild_id), server_config.guild_rate_limit),
+        ];
+        let limits = limits
+            .iter()
+            .filter_map(|(key, limit)| Some((key, (*limit)?)));
+
+        let mut wait = Duration::ZERO;
+        for (key, limit) in limits.clone() {
+            let mut bucket = self.buckets.entry(*key).or_insert_with(|| Bucket {
+                tokens: f64::from(limit.requests.get()),
+                updated: now,
+                limit,
+            });
+            bucket.refill(limit, now);
+
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64(
+                    (1.0 - bucket.tokens) / limit.tokens_per_sec(),
+                ));
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (key, _) in limits {
+            if let Some(mut bucket) = self.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the buckets that have refilled completely, at most once every
+    /// `PRUNE_INTERVAL`, since they'd be recreated the same
+    fn prune(&self, now: Instant) {
+        {
+            let mut last_pruned = self.last_pruned.lock().unwrap();
+            if last_pruned.is_some_and(|last| now.saturating_duration_since(last) < PRUNE_INTERVAL)
+            {
+                return;
+            }
+            *last_pruned = Some(now);
+        }
+
+        self.buckets.retain(|_, bucket| !bucket.is_full(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PRUNE_INTERVAL, RateLimit, RateLimiter};
+    use crate::database::Record;
+    use poise::serenity_prelude as serenity;
+    use std::num::NonZeroU32;
+    use std::time::{Duration, Instant};
+
+    fn limit(requests: u32, period_secs: u32) -> RateLimit {
+        RateLimit {
+            requests: NonZeroU32::new(requests).unwrap(),
+            period_secs: NonZeroU32::new(period_secs).unwrap(),
+        }
+    }
+
+    fn acquire(
+        limiter: &RateLimiter,
+        cfg: &Record,
+        user: u64,
+        roles: &[serenity::RoleId],
+        now: Instant,
+    ) -> Result<(), Duration> {
+        limiter.acquire(
+            cfg,
+            serenity::GuildId::new(1),
+            serenity::ChannelId::new(2),
+            serenity::UserId::new(user),
+            roles,
+            now,
+        )
+    }
+
+    #[test]
+    fn no_limits_never_limited() {
+        let limiter = RateLimiter::default();
+        let cfg = Record::default();
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        }
+    }
+
+    #[test]
+    fn user_bucket_empties_and_refills() {
+        let limiter = RateLimiter::default();
+        let cfg = Record {
+            user_rate_limit: Some(limit(2, 60)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        assert_eq!(
+            acquire(&limiter, &cfg, 3, &[], now),
+            Err(Duration::from_secs(30))
+        );
+
+        // Other users have their own bucket
+        assert!(acquire(&limiter, &cfg, 4, &[], now).is_ok());
+
+        assert!(acquire(&limiter, &cfg, 3, &[], now + Duration::from_secs(30)).is_ok());
+    }
+
+    #[test]
+    fn denied_request_uses_no_tokens() {
+        let limiter = RateLimiter::default();
+        let cfg = Record {
+            user_rate_limit: Some(limit(2, 60)),
+            guild_rate_limit: Some(limit(1, 60)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_err());
+
+        // The user's second token wasn't spent on the denied request
+        let cfg = Record {
+            guild_rate_limit: None,
+            ..cfg
+        };
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+    }
+
+    #[test]
+    fn exempt_roles_not_limited() {
+        let limiter = RateLimiter::default();
+        let cfg = Record {
+            guild_rate_limit: Some(limit(1, 60)),
+            rate_limit_exempt_role_ids: [5].into(),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let exempt = [serenity::RoleId::new(5)];
+
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_err());
+        assert!(acquire(&limiter, &cfg, 3, &exempt, now).is_ok());
+    }
+
+    #[test]
+    fn refilled_buckets_pruned() {
+        let limiter = RateLimiter::default();
+        let cfg = Record {
+            user_rate_limit: Some(limit(2, 60)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        assert!(acquire(&limiter, &cfg, 3, &[], now).is_ok());
+        assert!(acquire(&limiter, &cfg, 4, &[], now + Duration::from_secs(1)).is_ok());
+        assert_eq!(limiter.buckets.len(), 2);
+
+        // Both buckets have refilled, so only the one used again is kept
+        let later = now + PRUNE_INTERVAL;
+        assert!(acquire(&limiter, &cfg, 4, &[], later).is_ok());
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+}
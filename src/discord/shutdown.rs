@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+
+/// Lets the bot stop taking new messages, and wait for the channel tasks to
+/// finish handling the ones they already have
+#[derive(Default)]
+pub struct Shutdown {
+    started: AtomicBool,
+    handler_tasks: Mutex<JoinSet<()>>,
+}
+
+impl Shutdown {
+    /// Whether messages should be ignored, because the bot is shutting down
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Acquire)
+    }
+
+    /// Spawns a channel's task, so it can be waited for during shutdown.
+    /// Returns false without spawning it if shutdown has started, since it
+    /// wouldn't be waited for.
+    pub fn spawn_handler_task(&self, task: impl Future<Output = ()> + Send + 'static) -> bool {
+        let mut tasks = self.handler_tasks.lock().unwrap();
+
+        // Checked under the lock, so `drain` can't take the tasks in between
+        if self.is_started() {
+            return false;
+        }
+
+        // Forget tasks that already exited, e.g. ones that were restarted
+        while tasks.try_join_next().is_some() {}
+
+        tasks.spawn(task);
+        true
+    }
+
+    /// Stops new messages from being accepted and calls `close_channels`,
+    /// which should drop every task's sender so it exits once its queue is
+    /// empty. Then waits up to `timeout` for the tasks, aborting any that are
+    /// left, and returns how many that was.
+    pub async fn drain(&self, close_channels: impl FnOnce(), timeout: Duration) -> usize {
+        self.started.store(true, Ordering::Release);
+        close_channels();
+
+        let mut tasks = std::mem::take(&mut *self.handler_tasks.lock().unwrap());
+        log::info!("Waiting for {} channel task(s) to finish", tasks.len());
+
+        if tokio::time::timeout(timeout, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await
+        .is_err()
+        {
+            log::warn!(
+                "{} channel task(s) didn't finish within {timeout:?}, aborting them",
+                tasks.len()
+            );
+        }
+
+        let aborted = tasks.len();
+        tasks.shutdown().await;
+        aborted
+    }
+}
+
+/// Resolves once the process is asked to stop, with SIGTERM or Ctrl-C
+pub async fn signal() {
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        Ok(sigterm) => Some(sigterm),
+        Err(e) => {
+            log::error!("Couldn't listen for SIGTERM ({e})");
+            None
+        }
+    };
+
+    let sigterm = async {
+        match &mut sigterm {
+            Some(sigterm) => {
+                sigterm.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        () = sigterm => log::info!("Received SIGTERM"),
+        result = tokio::signal::ctrl_c() => match result {
+            Ok(()) => log::info!("Received Ctrl-C"),
+            Err(e) => {
+                log::error!("Couldn't listen for Ctrl-C ({e})");
+                std::future::pending::<()>().await;
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn queued_messages_handled_before_tasks_exit() {
+        let shutdown = Shutdown::default();
+        let (tx, mut rx) = mpsc::channel::<u32>(8);
+        let (handled_tx, mut handled) = mpsc::unbounded_channel();
+
+        assert!(shutdown.spawn_handler_task(async move {
+            while let Some(n) = rx.recv().await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                handled_tx.send(n).unwrap();
+            }
+        }));
+        for n in 0..3 {
+            tx.send(n).await.unwrap();
+        }
+
+        let aborted = shutdown
+            .drain(move || drop(tx), Duration::from_secs(5))
+            .await;
+
+        assert_eq!(aborted, 0);
+        assert!(shutdown.is_started());
+        for n in 0..3 {
+            assert_eq!(handled.try_recv().unwrap(), n);
+        }
+    }
+
+    #[tokio::test]
+    async fn stuck_tasks_aborted_after_timeout() {
+        let shutdown = Shutdown::default();
+        shutdown.spawn_handler_task(std::future::pending());
+
+        let aborted = shutdown.drain(|| {}, Duration::from_millis(10)).await;
+
+        assert_eq!(aborted, 1);
+    }
+
+    #[tokio::test]
+    async fn no_tasks_spawned_once_draining() {
+        let shutdown = Shutdown::default();
+        shutdown.drain(|| {}, Duration::from_millis(10)).await;
+
+        assert!(!shutdown.spawn_handler_task(async {}));
+    }
+}
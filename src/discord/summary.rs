@@ -0,0 +1,412 @@
+#![allow(clippy::result_large_err)]
+
+use std::fmt::Write;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use itertools::Itertools;
+use poise::serenity_prelude as serenity;
+
+use crate::claude;
+use crate::database::Record;
+use crate::discord::ask::{
+    ClaudeReply, Output, acquire_rate_limit, check_access, error_reply, request_reply, send_texts,
+};
+use crate::discord::error_reply::ErrorReply;
+use crate::discord::event_handlers::feedback::{FeedbackSource, track_feedback};
+use crate::discord::{CommandError, OutgoingMentions, PoiseContext};
+
+/// The most messages summarized at once
+const SUMMARY_HISTORY_LIMIT: u16 = 2000;
+
+/// How many messages `/summarize` covers when not told otherwise
+const DEFAULT_SUMMARY_LENGTH: u16 = 100;
+
+/// The most messages Discord returns per request
+const PAGE_SIZE: u8 = 100;
+
+/// The longest transcript (in bytes) summarized in a single request
+const CHUNK_LENGTH: usize = 40_000;
+
+const PARTIAL_SUMMARY_PROMPT: &str = "*Above is part of a Discord conversation, or summaries of \
+    consecutive parts of one. Summarize it for someone who missed it, covering the main topics, \
+    any decisions that were made, and any open questions. Keep the tags (like [m12]) of the most \
+    important messages next to what they're about.*";
+
+const SUMMARY_PROMPT: &str = "*Above is a Discord conversation, or summaries of consecutive parts \
+    of one. Summarize it for someone who missed it: start with a one or two sentence overview, \
+    then bullet points under **Topics**, **Decisions**, and **Open questions** headings, leaving \
+    out empty sections. Cite the most important messages by their tags (like [m12]) right after \
+    what they're about.*";
+
+/// Up to `limit` of the latest messages in `channel_id` sent before `before`
+/// and after `since`, oldest first
+async fn latest_history(
+    ctx: PoiseContext<'_>,
+    channel_id: serenity::ChannelId,
+    before: serenity::MessageId,
+    limit: usize,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<serenity::Message>, CommandError> {
+    let mut history: Vec<serenity::Message> = vec![];
+
+    while history.len() < limit {
+        let before = history.last().map_or(before, |m| m.id);
+        let page = channel_id
+            .messages(
+                ctx,
+                serenity::GetMessages::new().before(before).limit(PAGE_SIZE),
+            )
+            .await?;
+
+        let full_page = page.len() == usize::from(PAGE_SIZE);
+        let page_len = page.len();
+        let previous_len = history.len();
+
+        // Pages come newest first
+        history.extend(
+            page.into_iter()
+                .take_while(|m| since.is_none_or(|since| *m.timestamp >= since)),
+        );
+
+        if !full_page || history.len() - previous_len < page_len {
+            break;
+        }
+    }
+
+    history.truncate(limit);
+    history.reverse();
+    Ok(history)
+}
+
+/// `from` and up to `limit - 1` of the messages after it, oldest first
+async fn history_from(
+    ctx: PoiseContext<'_>,
+    from: &serenity::Message,
+    limit: usize,
+) -> Result<Vec<serenity::Message>, CommandError> {
+    let mut history = vec![from.clone()];
+
+    while history.len() < limit {
+        let after = history.last().map_or(from.id, |m| m.id);
+        let page = from
+            .channel_id
+            .messages(
+                ctx,
+                serenity::GetMessages::new().after(after).limit(PAGE_SIZE),
+            )
+            .await?;
+
+        let full_page = page.len() == usize::from(PAGE_SIZE);
+        history.extend(page.into_iter().sorted_by_key(|m| m.id));
+
+        if !full_page {
+            break;
+        }
+    }
+
+    history.truncate(limit);
+    Ok(history)
+}
+
+/// Joins `lines` into transcripts of at most `max_length` bytes, except for
+/// single lines that are already longer
+fn chunk_lines(lines: &[String], max_length: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = vec![];
+
+    for line in lines {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + 1 + line.len() <= max_length => {
+                chunk.push('\n');
+                chunk.push_str(line);
+            }
+            _ => chunks.push(line.clone()),
+        }
+    }
+
+    chunks
+}
+
+/// Replaces `[mN]` tags with links to the `N`th (1-indexed) message
+fn link_tags(text: &str, links: &[String]) -> String {
+    let mut linked = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("[m") {
+        linked.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let link = after.split_once(']').and_then(|(n, _)| {
+            let index: usize = n.parse().ok()?;
+            Some((n.len(), links.get(index.checked_sub(1)?)?))
+        });
+
+        if let Some((n_len, link)) = link {
+            write!(linked, "[↗](<{link}>)").unwrap();
+            rest = &after[n_len + 1..];
+        } else {
+            linked.push_str("[m");
+            rest = after;
+        }
+    }
+
+    linked.push_str(rest);
+    linked
+}
+
+/// Asks Claude to summarize `transcript` with `prompt`, adding to `usage`.
+/// Every request uses up one of the invoker's requests.
+async fn request_summary(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    role_ids: &[serenity::RoleId],
+    transcript: &str,
+    prompt: &str,
+    usage: &mut claude::Usage,
+) -> Result<Vec<String>, ErrorReply> {
+    let msg = claude::Message::user_text(&format!(
+        "<transcript>\n{transcript}\n</transcript>\n\n{prompt}"
+    ));
+
+    acquire_rate_limit(ctx, config, role_ids)?;
+    let reply = request_reply(ctx, config, &[msg]).await?;
+
+    usage.input_tokens += reply.usage.input_tokens;
+    usage.output_tokens += reply.usage.output_tokens;
+
+    Ok(reply.texts)
+}
+
+/// Summarizes `history`, splitting it up and summarizing the summaries of
+/// each part when it's too long for one request
+async fn summarize(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    role_ids: &[serenity::RoleId],
+    history: &[serenity::Message],
+) -> Result<ClaudeReply, ErrorReply> {
+    let local_time = config.local_time();
+    let cache = &ctx.serenity_context().cache;
+
+    let lines = history
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let formatted = claude::Message::format_message(m, cache, local_time);
+            format!("[m{}] {formatted}", i + 1)
+        })
+        .collect_vec();
+
+    let mut usage = claude::Usage::default();
+    let mut chunks = chunk_lines(&lines, CHUNK_LENGTH);
+
+    while chunks.len() > 1 {
+        let mut summaries = vec![];
+        for chunk in &chunks {
+            let texts = request_summary(
+                ctx,
+                config,
+                role_ids,
+                chunk,
+                PARTIAL_SUMMARY_PROMPT,
+                &mut usage,
+            )
+            .await?;
+            summaries.push(texts.join("\n"));
+        }
+
+        // Summaries that don't shrink would otherwise be summarized forever
+        let next_chunks = chunk_lines(&summaries, CHUNK_LENGTH);
+        if next_chunks.len() >= chunks.len() {
+            return Err(ErrorReply::TooMuchToSummarize);
+        }
+        chunks = next_chunks;
+    }
+
+    let Some(transcript) = chunks.first() else {
+        return Err(ErrorReply::NothingToSummarize);
+    };
+
+    let message_links = history.iter().map(serenity::Message::link).collect_vec();
+    let texts = request_summary(
+        ctx,
+        config,
+        role_ids,
+        transcript,
+        SUMMARY_PROMPT,
+        &mut usage,
+    )
+    .await?
+    .iter()
+    .map(|txt| link_tags(txt, &message_links))
+    .collect();
+
+    Ok(ClaudeReply { texts, usage })
+}
+
+/// Summarizes `history` and replies to the (deferred) interaction with it
+async fn reply_with_summary(
+    ctx: PoiseContext<'_>,
+    config: &Record,
+    role_ids: &[serenity::RoleId],
+    history: &[serenity::Message],
+    output: Output,
+    trigger: &str,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let reply = match summarize(ctx, config, role_ids, history).await {
+        Ok(r) => r,
+        Err(reply) => return error_reply(ctx, reply).await,
+    };
+
+    let mentions = OutgoingMentions::new(history, config.mention_policy.clone());
+    let sent = send_texts(ctx, &reply.texts, &mentions, output).await?;
+
+    track_feedback(
+        &ctx.data().db,
+        &ctx.data().claude,
+        &FeedbackSource {
+            guild_id,
+            channel_id: ctx.channel_id(),
+            trigger_message_id: output
+                .reply_to
+                .unwrap_or(serenity::MessageId::new(ctx.id())),
+            trigger,
+            model: &config.model,
+            usage: reply.usage,
+        },
+        &sent,
+    )?;
+
+    Ok(())
+}
+
+/// Summarizes recent messages in this channel
+#[poise::command(slash_command, guild_only, rename = "summarize")]
+pub async fn summarize_channel(
+    ctx: PoiseContext<'_>,
+    #[description = "How many of the latest messages to summarize (default: 100)"]
+    #[min = 1]
+    #[max = 2000]
+    messages: Option<u16>,
+    #[description = "Summarize messages from this many hours ago onward"]
+    #[min = 1]
+    #[max = 168]
+    hours: Option<u16>,
+    #[description = "Whether only you can see the summary (default: yes)"] ephemeral: Option<bool>,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    let ephemeral = ephemeral.unwrap_or(true);
+    if ephemeral {
+        ctx.defer_ephemeral().await?;
+    } else {
+        ctx.defer().await?;
+    }
+
+    let config = ctx.data().db.get_config(guild_id.get())?;
+    let role_ids = match check_access(ctx, &config).await {
+        Ok(role_ids) => role_ids,
+        Err(reply) => return error_reply(ctx, reply).await,
+    };
+
+    let limit = match (messages, hours) {
+        (Some(n), _) => n,
+        (None, Some(_)) => SUMMARY_HISTORY_LIMIT,
+        (None, None) => DEFAULT_SUMMARY_LENGTH,
+    };
+    let since = hours.map(|h| Utc::now() - TimeDelta::hours(i64::from(h)));
+
+    // Messages from before the command was used, which excludes the deferred
+    // response
+    let history = latest_history(
+        ctx,
+        ctx.channel_id(),
+        serenity::MessageId::new(ctx.id()),
+        usize::from(limit),
+        since,
+    )
+    .await?;
+
+    let output = Output {
+        ephemeral,
+        reply_to: None,
+    };
+
+    reply_with_summary(
+        ctx,
+        &config,
+        &role_ids,
+        &history,
+        output,
+        "summarize_command",
+    )
+    .await
+}
+
+/// Has Claude summarize everything from a message onward
+#[poise::command(context_menu_command = "Summarize from here", guild_only)]
+pub async fn summarize_from_here(
+    ctx: PoiseContext<'_>,
+    #[description = "First message to summarize"] message: serenity::Message,
+) -> Result<(), CommandError> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.say("Couldn't get server id").await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let config = ctx.data().db.get_config(guild_id.get())?;
+    let role_ids = match check_access(ctx, &config).await {
+        Ok(role_ids) => role_ids,
+        Err(reply) => return error_reply(ctx, reply).await,
+    };
+
+    let history = history_from(ctx, &message, usize::from(SUMMARY_HISTORY_LIMIT)).await?;
+
+    let output = Output {
+        ephemeral: true,
+        reply_to: Some(message.id),
+    };
+
+    reply_with_summary(ctx, &config, &role_ids, &history, output, "summarize_menu").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunk_lines, link_tags};
+
+    #[test]
+    fn lines_chunked_under_max_length() {
+        let lines = ["aaaa", "bbbb", "cccc", "dddddddddddd"].map(String::from);
+
+        assert_eq!(
+            chunk_lines(&lines, 9),
+            vec!["aaaa\nbbbb", "cccc", "dddddddddddd"]
+        );
+        assert!(chunk_lines(&[], 9).is_empty());
+    }
+
+    #[test]
+    fn tags_become_links() {
+        let links = ["https://a".to_string(), "https://b".to_string()];
+
+        assert_eq!(
+            link_tags("see [m2] and [m1].", &links),
+            "see [↗](<https://b>) and [↗](<https://a>)."
+        );
+    }
+
+    #[test]
+    fn unknown_tags_unchanged() {
+        let links = ["https://a".to_string()];
+
+        assert_eq!(link_tags("[m0] [m2] [mx] [m", &links), "[m0] [m2] [mx] [m");
+    }
+}
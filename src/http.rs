@@ -0,0 +1,149 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use thiserror::Error;
+use tokio::net::TcpListener;
+
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("Couldn't listen on {0} ({1})")]
+    Bind(SocketAddr, std::io::Error),
+}
+
+/// What to answer a `GET` for a path with
+pub struct Reply {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Answers `GET` requests on `address` with `route`, which resolves to `None`
+/// for paths it doesn't know
+pub async fn serve<F, Fut>(address: SocketAddr, route: F) -> Result<(), HttpError>
+where
+    F: Fn(String) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Option<Reply>> + Send,
+{
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|e| HttpError::Bind(address, e))?;
+    log::info!("Listening on http://{address}");
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("Couldn't accept connection on {address} ({e})");
+                    continue;
+                }
+            };
+
+            let route = route.clone();
+            let service = service_fn(move |request: Request<hyper::body::Incoming>| {
+                let route = route.clone();
+                async move { Ok::<_, Infallible>(respond(&request, route).await) }
+            });
+
+            tokio::spawn(async move {
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    log::debug!("HTTP connection on {address} failed ({e})");
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn respond<B, Fut>(
+    request: &Request<B>,
+    route: impl Fn(String) -> Fut,
+) -> Response<Full<Bytes>>
+where
+    Fut: Future<Output = Option<Reply>>,
+{
+    let reply = match (
+        request.method(),
+        route(request.uri().path().to_string()).await,
+    ) {
+        (&Method::GET, Some(reply)) => reply,
+        (_, Some(_)) => Reply {
+            status: StatusCode::METHOD_NOT_ALLOWED,
+            content_type: "text/plain",
+            body: "Method not allowed\n".to_string(),
+        },
+        (_, None) => Reply {
+            status: StatusCode::NOT_FOUND,
+            content_type: "text/plain",
+            body: "Not found\n".to_string(),
+        },
+    };
+
+    Response::builder()
+        .status(reply.status)
+        .header("content-type", reply.content_type)
+        .body(Full::new(Bytes::from(reply.body)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reply, respond, serve};
+    use hyper::{Request, StatusCode};
+
+    async fn route(path: String) -> Option<Reply> {
+        (path == "/known").then(|| Reply {
+            status: StatusCode::OK,
+            content_type: "text/plain",
+            body: "ok".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn only_gets_for_known_paths_answered() {
+        let get = |path| Request::get(path).body(()).unwrap();
+
+        assert_eq!(
+            respond(&get("/known"), route).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            respond(&get("/unknown"), route).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            respond(&Request::post("/known").body(()).unwrap(), route)
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn served_over_http() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        serve(address, route).await.unwrap();
+
+        let response = reqwest::get(format!("http://{address}/known"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        assert!(serve(address, route).await.is_err());
+    }
+}
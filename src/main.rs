@@ -1,24 +1,95 @@
 mod arg_parse;
 mod claude;
+mod config;
 mod database;
 mod discord;
+mod http;
+mod metrics;
+mod secret;
+mod subcommand;
 
+use anyhow::Context;
 use clap::Parser;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = arg_parse::Args::parse();
 
-    tracing_subscriber::fmt()
-        .with_max_level(args.log_level)
-        .init();
+    let subscriber = tracing_subscriber::fmt().with_max_level(args.log_level);
+    match args.log_format {
+        arg_parse::LogFormat::Text => subscriber.init(),
+        arg_parse::LogFormat::Json => subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
 
-    let db_client = database::Client::new(&args.database_path)?;
+    let config = args.config.unwrap_or_default();
 
-    let claude_client = claude::Client::new();
+    let master_key = secret::MASTER_KEY.resolve(args.master_key_file.as_ref())?;
+
+    let mut db_client = database::Client::new(&args.database_path)?
+        .with_new_server_config(config.new_server.record());
+    if let Some(master_key) = &master_key {
+        db_client = db_client.with_master_key(database::MasterKey::new(master_key.expose())?)?;
+    }
+
+    if let Some(command) = args.command {
+        return subcommand::run(command, &db_client);
+    }
+
+    let discord_token = secret::DISCORD_TOKEN
+        .resolve(args.discord_token_file.as_ref())?
+        .context(
+            "A Discord token is required to run the bot (--discord-token-file, $CLAUDE_DISCORD_BOT_DISCORD_TOKEN or the discord-token credential)",
+        )?;
+
+    master_key.context(
+        "A master key is required to encrypt Claude API keys (--master-key-file, $CLAUDE_DISCORD_BOT_MASTER_KEY or the master-key credential)",
+    )?;
+    db_client.verify_master_key()?;
+
+    let default_api_key =
+        match secret::DEFAULT_API_KEY.resolve(args.default_api_key_file.as_ref())? {
+            Some(api_key) => discord::DefaultApiKey::new(
+                api_key.expose(),
+                args.default_api_key_guild_ids.into_iter().collect(),
+                args.default_api_key_daily_tokens,
+            ),
+            None if !args.default_api_key_guild_ids.is_empty()
+                || args.default_api_key_daily_tokens.is_some() =>
+            {
+                anyhow::bail!("Servers can only be allowed a default API key if one is given");
+            }
+            None => discord::DefaultApiKey::default(),
+        };
+
+    let claude_client = claude::Client::with_config(&config.claude);
+
+    let mut bot = discord::Bot::new(
+        discord_token.expose(),
+        db_client,
+        claude_client,
+        default_api_key,
+        &config.discord,
+    )
+    .await?;
+
+    if let Some(address) = args.metrics_address {
+        bot.serve_metrics(address).await?;
+    }
+
+    if let Some(address) = args.health_address {
+        bot.serve_health(address).await?;
+    }
 
-    let mut bot = discord::Bot::new(&args.discord_token_file, db_client, claude_client).await?;
     bot.run().await?;
 
+    // The channel tasks were drained, so every write was committed
+    drop(bot);
+    log::info!("Shut down");
+
     Ok(())
 }
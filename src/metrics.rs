@@ -0,0 +1,200 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::claude::{self, ClaudeError};
+
+/// Seconds, covering quick refusals up to long responses from the largest
+/// models
+const LATENCY_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 40.0, 60.0, 120.0, 300.0];
+
+struct Metrics {
+    registry: Registry,
+    claude_requests: IntCounterVec,
+    claude_request_duration: HistogramVec,
+    claude_tokens: IntCounterVec,
+    claude_last_success: IntGauge,
+    discord_send_failures: IntCounterVec,
+    error_replies: IntCounterVec,
+    channel_tasks: IntGauge,
+    channel_queue_depth: IntGaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry = Registry::new_custom(Some("claude_discord_bot".to_string()), None).unwrap();
+
+    let metrics = Metrics {
+        claude_requests: IntCounterVec::new(
+            Opts::new("claude_requests_total", "Requests to Claude"),
+            &["model", "outcome", "stop_reason"],
+        )
+        .unwrap(),
+        claude_request_duration: HistogramVec::new(
+            HistogramOpts::new(
+                "claude_request_duration_seconds",
+                "How long requests to Claude took",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model"],
+        )
+        .unwrap(),
+        claude_tokens: IntCounterVec::new(
+            Opts::new("claude_tokens_total", "Tokens used by requests to Claude"),
+            &["model", "kind"],
+        )
+        .unwrap(),
+        claude_last_success: IntGauge::new(
+            "claude_last_success_timestamp_seconds",
+            "When the last successful request to Claude finished, as a Unix timestamp",
+        )
+        .unwrap(),
+        discord_send_failures: IntCounterVec::new(
+            Opts::new(
+                "discord_send_failures_total",
+                "Messages, replies and reactions that couldn't be sent to Discord",
+            ),
+            &["operation"],
+        )
+        .unwrap(),
+        error_replies: IntCounterVec::new(
+            Opts::new("error_replies_total", "Error replies sent, by kind"),
+            &["kind"],
+        )
+        .unwrap(),
+        channel_tasks: IntGauge::new("channel_tasks_active", "Running channel tasks").unwrap(),
+        channel_queue_depth: IntGaugeVec::new(
+            Opts::new(
+                "channel_queue_depth",
+                "Messages waiting for each channel's task",
+            ),
+            &["channel_id"],
+        )
+        .unwrap(),
+        registry,
+    };
+
+    let collectors: [Box<dyn prometheus::core::Collector>; 8] = [
+        Box::new(metrics.claude_requests.clone()),
+        Box::new(metrics.claude_request_duration.clone()),
+        Box::new(metrics.claude_tokens.clone()),
+        Box::new(metrics.claude_last_success.clone()),
+        Box::new(metrics.discord_send_failures.clone()),
+        Box::new(metrics.error_replies.clone()),
+        Box::new(metrics.channel_tasks.clone()),
+        Box::new(metrics.channel_queue_depth.clone()),
+    ];
+    for collector in collectors {
+        metrics.registry.register(collector).unwrap();
+    }
+
+    metrics
+});
+
+/// A request to Claude with `model` that took `elapsed`
+pub fn record_claude_request(
+    model: &claude::Model,
+    result: &Result<claude::Response, ClaudeError>,
+    elapsed: Duration,
+) {
+    let model = model.id();
+
+    let (outcome, stop_reason) = match result {
+        Ok(response) => ("success", response.stop_reason.name()),
+        Err(ClaudeError::Http(_)) => ("http_error", ""),
+        Err(ClaudeError::Parse(_)) => ("parse_error", ""),
+        Err(ClaudeError::Status(_)) => ("status_error", ""),
+    };
+
+    METRICS
+        .claude_requests
+        .with_label_values(&[model, outcome, stop_reason])
+        .inc();
+    METRICS
+        .claude_request_duration
+        .with_label_values(&[model])
+        .observe(elapsed.as_secs_f64());
+
+    if let Ok(response) = result {
+        METRICS
+            .claude_last_success
+            .set(chrono::Utc::now().timestamp());
+        METRICS
+            .claude_tokens
+            .with_label_values(&[model, "input"])
+            .inc_by(response.usage.input_tokens);
+        METRICS
+            .claude_tokens
+            .with_label_values(&[model, "output"])
+            .inc_by(response.usage.output_tokens);
+    }
+}
+
+/// When the last successful request to Claude finished, if there was one
+pub fn last_claude_success() -> Option<DateTime<Utc>> {
+    match METRICS.claude_last_success.get() {
+        0 => None,
+        timestamp => DateTime::from_timestamp(timestamp, 0),
+    }
+}
+
+/// Something couldn't be sent to Discord, e.g. a `message` or `reaction`
+pub fn record_send_failure(operation: &str) {
+    METRICS
+        .discord_send_failures
+        .with_label_values(&[operation])
+        .inc();
+}
+
+pub fn record_error_reply(kind: &str) {
+    METRICS.error_replies.with_label_values(&[kind]).inc();
+}
+
+/// Replaces the channel task gauges with how many messages are waiting for
+/// each running task
+pub fn set_channel_queues(queue_depths: impl IntoIterator<Item = (u64, usize)>) {
+    METRICS.channel_queue_depth.reset();
+
+    let mut tasks = 0;
+    for (channel_id, depth) in queue_depths {
+        tasks += 1;
+        METRICS
+            .channel_queue_depth
+            .with_label_values(&[&channel_id.to_string()])
+            .set(i64::try_from(depth).unwrap_or(i64::MAX));
+    }
+
+    METRICS.channel_tasks.set(tasks);
+}
+
+/// Every metric, in Prometheus' text format
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .unwrap();
+
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record_error_reply, render, set_channel_queues};
+
+    #[test]
+    fn rendered_with_prefix() {
+        record_error_reply("max_tokens");
+        set_channel_queues([(1, 3), (2, 0)]);
+        set_channel_queues([(1, 2)]);
+
+        let rendered = render();
+        assert!(rendered.contains(r#"claude_discord_bot_error_replies_total{kind="max_tokens"}"#));
+        assert!(rendered.contains("claude_discord_bot_channel_tasks_active 1"));
+        assert!(rendered.contains(r#"claude_discord_bot_channel_queue_depth{channel_id="1"} 2"#));
+        assert!(!rendered.contains(r#"channel_id="2""#));
+    }
+}
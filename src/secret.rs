@@ -0,0 +1,147 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// A token or key, which is never printed
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(s: &str) -> Self {
+        Self(s.trim().to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("${0} is set, but empty")]
+    EmptyEnvVar(&'static str),
+
+    #[error("Cannot read credential '{0}' ({1})")]
+    ReadCredential(PathBuf, std::io::Error),
+
+    #[error("Credential '{0}' is an empty file")]
+    EmptyCredential(PathBuf),
+}
+
+/// Where a secret can be found when its `--*-file` flag isn't given
+pub struct SecretSource {
+    /// Environment variable holding the secret itself
+    pub env_var: &'static str,
+    /// Name of the file in `$CREDENTIALS_DIRECTORY`, as given to systemd's
+    /// `LoadCredential`
+    pub credential: &'static str,
+}
+
+pub const DISCORD_TOKEN: SecretSource = SecretSource {
+    env_var: "CLAUDE_DISCORD_BOT_DISCORD_TOKEN",
+    credential: "discord-token",
+};
+
+pub const MASTER_KEY: SecretSource = SecretSource {
+    env_var: "CLAUDE_DISCORD_BOT_MASTER_KEY",
+    credential: "master-key",
+};
+
+pub const DEFAULT_API_KEY: SecretSource = SecretSource {
+    env_var: "CLAUDE_DISCORD_BOT_DEFAULT_API_KEY",
+    credential: "default-api-key",
+};
+
+impl SecretSource {
+    /// The secret from its `--*-file` flag if that was given, otherwise from
+    /// the environment variable, otherwise from `$CREDENTIALS_DIRECTORY`
+    pub fn resolve(&self, from_file: Option<&Secret>) -> Result<Option<Secret>, SecretError> {
+        self.resolve_from(
+            from_file,
+            std::env::var(self.env_var).ok(),
+            std::env::var_os("CREDENTIALS_DIRECTORY")
+                .map(PathBuf::from)
+                .as_deref(),
+        )
+    }
+
+    fn resolve_from(
+        &self,
+        from_file: Option<&Secret>,
+        env_value: Option<String>,
+        credentials_dir: Option<&Path>,
+    ) -> Result<Option<Secret>, SecretError> {
+        if let Some(secret) = from_file {
+            return Ok(Some(secret.clone()));
+        }
+
+        if let Some(value) = env_value {
+            return match value.trim() {
+                "" => Err(SecretError::EmptyEnvVar(self.env_var)),
+                value => Ok(Some(Secret::new(value))),
+            };
+        }
+
+        let Some(path) = credentials_dir.map(|dir| dir.join(self.credential)) else {
+            return Ok(None);
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) if contents.trim().is_empty() => Err(SecretError::EmptyCredential(path)),
+            Ok(contents) => Ok(Some(Secret::new(&contents))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(SecretError::ReadCredential(path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DISCORD_TOKEN, Secret, SecretError};
+
+    #[test]
+    fn flag_then_env_var_then_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("discord-token"), "from-credential\n").unwrap();
+        let flag = Secret::new("from-flag");
+
+        let resolve = |flag, env: Option<&str>, dir| {
+            DISCORD_TOKEN
+                .resolve_from(flag, env.map(str::to_string), dir)
+                .unwrap()
+                .map(|s| s.expose().to_string())
+        };
+
+        assert_eq!(
+            resolve(Some(&flag), Some("from-env"), Some(dir.path())).as_deref(),
+            Some("from-flag")
+        );
+        assert_eq!(
+            resolve(None, Some("from-env"), Some(dir.path())).as_deref(),
+            Some("from-env")
+        );
+        assert_eq!(
+            resolve(None, None, Some(dir.path())).as_deref(),
+            Some("from-credential")
+        );
+        assert_eq!(resolve(None, None, None), None);
+
+        assert!(matches!(
+            DISCORD_TOKEN.resolve_from(None, Some(" ".to_string()), None),
+            Err(SecretError::EmptyEnvVar(_))
+        ));
+    }
+
+    #[test]
+    fn never_printed() {
+        let secret = Secret::new("sk-ant-secret");
+        assert!(!format!("{secret:?}").contains("sk-ant"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::{Context, bail};
+use itertools::Itertools;
+
+use crate::arg_parse::{Command, DbCommand};
+use crate::database::{self, FeedbackRecord, MasterKey, Record};
+
+/// Who config changes made with `db edit` are attributed to in the audit log
+const OPERATOR: u64 = 0;
+
+pub fn run(command: Command, db: &database::Client) -> anyhow::Result<()> {
+    match command {
+        Command::ExportFeedback { guild_id, output } => {
+            let jsonl = FeedbackRecord::to_jsonl(&db.get_feedback(guild_id)?)?;
+            write_output(output, &jsonl)?;
+        }
+        Command::RotateMasterKey {
+            new_master_key_file,
+        } => {
+            let rotated = db.rotate_master_key(&MasterKey::new(new_master_key_file.expose())?)?;
+            println!("Re-encrypted {rotated} Claude API key(s)");
+        }
+        Command::Db(command) => run_db(command, db)?,
+    }
+
+    Ok(())
+}
+
+fn run_db(command: DbCommand, db: &database::Client) -> anyhow::Result<()> {
+    match command {
+        DbCommand::List => {
+            for (guild_id, config) in db.get_stored_configs()? {
+                println!(
+                    "{guild_id}\t{}\t{} active channel(s)\tAPI key {}",
+                    config.model,
+                    config.active_channel_ids.len(),
+                    if config.claude_api_key.is_some() {
+                        "set"
+                    } else {
+                        "not set"
+                    }
+                );
+            }
+        }
+        DbCommand::Show { guild_id } => {
+            let Some((_, mut config)) = db
+                .get_stored_configs()?
+                .into_iter()
+                .find(|(id, _)| *id == guild_id)
+            else {
+                bail!("Server {guild_id} has no stored config");
+            };
+
+            config.claude_api_key = config.claude_api_key.map(|_| "<redacted>".to_string());
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        DbCommand::Edit {
+            guild_id,
+            field,
+            value,
+        } => {
+            let config = edit_field(db.get_config(guild_id)?, &field, &value)?;
+            db.set_config(guild_id, OPERATOR, config)?;
+            println!("Set {field} of server {guild_id}");
+        }
+        DbCommand::Export { output } => {
+            let configs = db
+                .get_stored_configs()?
+                .into_iter()
+                .collect::<BTreeMap<_, _>>();
+            write_output(output, &serde_json::to_string_pretty(&configs)?)?;
+        }
+        DbCommand::Import { input } => {
+            let json = std::fs::read_to_string(&input)
+                .with_context(|| format!("Couldn't read '{}'", input.display()))?;
+            let configs: BTreeMap<u64, Record> = serde_json::from_str(&json)
+                .with_context(|| format!("'{}' isn't an exported config", input.display()))?;
+
+            let count = configs.len();
+            db.import_configs(configs.into_iter().collect(), OPERATOR)?;
+            println!("Imported {count} server config(s)");
+        }
+        DbCommand::Remove { guild_id } => {
+            if !db.remove_config(guild_id, OPERATOR)? {
+                bail!("Server {guild_id} has no stored config");
+            }
+            println!("Removed the config of server {guild_id}");
+        }
+    }
+
+    Ok(())
+}
+
+fn write_output(output: Option<std::path::PathBuf>, contents: &str) -> anyhow::Result<()> {
+    match output {
+        Some(path) => std::fs::write(&path, contents)
+            .with_context(|| format!("Couldn't write to '{}'", path.display()))?,
+        None => std::io::stdout().write_all(contents.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// `config` with `field` set to `value`, which is JSON or else a string
+fn edit_field(config: Record, field: &str, value: &str) -> anyhow::Result<Record> {
+    let mut json = serde_json::to_value(config)?;
+    let fields = json.as_object_mut().context("Config isn't a JSON object")?;
+
+    let Some(slot) = fields.get_mut(field) else {
+        bail!(
+            "Unknown field '{field}', expected one of {}",
+            fields.keys().join(", ")
+        );
+    };
+    *slot = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+
+    serde_json::from_value(json).with_context(|| format!("Invalid value '{value}' for {field}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edit_field;
+    use crate::database::Record;
+
+    #[test]
+    fn fields_edited_from_json_or_strings() {
+        let config = edit_field(Record::default(), "regenerate_on_edit", "true").unwrap();
+        assert!(config.regenerate_on_edit);
+
+        let config = edit_field(config, "model", "claude-opus-4-6").unwrap();
+        assert_eq!(config.model.id(), "claude-opus-4-6");
+
+        let config = edit_field(config, "active_channel_ids", "[1, 2]").unwrap();
+        assert_eq!(config.active_channel_ids, [1, 2].into());
+
+        assert!(edit_field(config.clone(), "nonexistent", "1").is_err());
+        assert!(edit_field(config, "regenerate_on_edit", "maybe").is_err());
+    }
+}